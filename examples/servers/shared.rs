@@ -44,15 +44,16 @@ fn composite_propagator() -> TextMapCompositePropagator {
 
 #[allow(dead_code)]
 pub fn trace_config(version: &str, instance_id: &str) -> Config {
+    // service, host, process, container and kubernetes attributes
+    let detected = opentelemetry_tide::detect_resource!();
+
     let tags = [
         resource::SERVICE_VERSION.string(version.to_owned()),
         resource::SERVICE_INSTANCE_ID.string(instance_id.to_owned()),
-        resource::PROCESS_EXECUTABLE_PATH.string(std::env::current_exe().unwrap().display().to_string()),
-        resource::PROCESS_PID.string(std::process::id().to_string()),
         KeyValue::new("process.executable.profile", PROFILE),
     ];
 
-    trace::config().with_resource(Resource::new(tags))
+    trace::config().with_resource(detected.merge(&Resource::new(tags)))
 }
 
 pub fn jaeger_tracer(svc_name: &str, version: &str, instance_id: &str) -> Result<Tracer, TraceError> {
@@ -0,0 +1,175 @@
+/*!
+Procedural macros for [opentelemetry-tide](https://crates.io/crates/opentelemetry-tide)
+
+Do not depend on this crate directly, but enable the `macros` feature of `opentelemetry-tide`
+and use the re-exported macros from there.
+*/
+#![forbid(unsafe_code)]
+#![deny(clippy::unwrap_used)]
+#![deny(missing_debug_implementations)]
+#![deny(missing_docs)]
+#![deny(unused_imports)]
+#![deny(unused_results)]
+#![warn(clippy::expect_used)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input, spanned::Spanned, AttributeArgs, FnArg, Ident, ItemFn, Lit, Meta, NestedMeta, Pat, ReturnType,
+    Type,
+};
+
+/**
+Instruments a function with an internal span, child of the currently active span.
+
+For tide handlers this is the server span of `OpenTelemetryTracingMiddleware`.
+
+The span is named after the function, unless a `name = "…"` is given.
+Arguments listed in `args(…)` are recorded as span attributes (formatted with `Debug`).
+If the function returns a `Result` (like `tide::Result`), an `Err` marks the span as error.
+
+Works for both `async` and regular functions.
+
+# Examples
+
+```rust,ignore
+#[opentelemetry_tide::instrument]
+async fn hello(_req: tide::Request<()>) -> tide::Result {
+    Ok("Hello!".into())
+}
+
+#[opentelemetry_tide::instrument(name = "user.load", args(id))]
+async fn load_user(id: u64) -> std::io::Result<String> {
+    Ok(format!("user {}", id))
+}
+```
+*/
+#[proc_macro_attribute]
+pub fn instrument(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(item as ItemFn);
+    match expand(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    name: Option<String>,
+    args: Vec<Ident>,
+}
+
+fn parse_options(args: AttributeArgs) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match nv.lit {
+                Lit::Str(name) => options.name = Some(name.value()),
+                lit => return Err(syn::Error::new(lit.span(), "expected a string literal")),
+            },
+            NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("args") => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(Meta::Path(path)) if path.get_ident().is_some() => {
+                            if let Some(ident) = path.get_ident() {
+                                options.args.push(ident.clone());
+                            }
+                        }
+                        other => return Err(syn::Error::new(other.span(), "expected an argument name")),
+                    }
+                }
+            }
+            other => {
+                return Err(syn::Error::new(
+                    other.span(),
+                    "unknown option; expected `name = \"…\"` or `args(…)`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn expand(args: AttributeArgs, item: ItemFn) -> syn::Result<TokenStream2> {
+    let options = parse_options(args)?;
+    let ItemFn { attrs, vis, sig, block } = item;
+
+    let fn_name = sig.ident.to_string();
+    let span_name = options.name.unwrap_or_else(|| fn_name.clone());
+
+    let params: Vec<&Ident> = sig
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            FnArg::Typed(typed) => match &*typed.pat {
+                Pat::Ident(pat) => Some(&pat.ident),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .collect();
+    let mut attributes = Vec::with_capacity(options.args.len());
+    for arg in &options.args {
+        if !params.contains(&arg) {
+            return Err(syn::Error::new(arg.span(), "not an argument of this function"));
+        }
+        let key = arg.to_string();
+        attributes.push(quote_spanned! {arg.span()=>
+            ::opentelemetry_tide::__private::KeyValue::new(#key, ::std::format!("{:?}", &#arg))
+        });
+    }
+
+    let returns_result = match &sig.output {
+        ReturnType::Type(_, ty) => is_result(ty),
+        ReturnType::Default => false,
+    };
+
+    let body = match (sig.asyncness.is_some(), returns_result) {
+        (true, true) => quote! {
+            ::opentelemetry_tide::__private::in_span_result(#span_name, #fn_name, ::std::module_path!(), __otel_attributes, async move #block).await
+        },
+        (true, false) => quote! {
+            ::opentelemetry_tide::__private::in_span(#span_name, #fn_name, ::std::module_path!(), __otel_attributes, async move #block).await
+        },
+        (false, returns_result) => {
+            // an explicit return type helps the type inference for `?` inside of the closure
+            let closure = match &sig.output {
+                ReturnType::Type(_, ty) if !matches!(**ty, Type::ImplTrait(_)) => quote! { move || -> #ty #block },
+                _ => quote! { move || #block },
+            };
+            let helper = if returns_result {
+                quote! { in_span_sync_result }
+            } else {
+                quote! { in_span_sync }
+            };
+            quote! {
+                ::opentelemetry_tide::__private::#helper(#span_name, #fn_name, ::std::module_path!(), __otel_attributes, #closure)
+            }
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            let __otel_attributes = ::std::vec![#(#attributes),*];
+            #body
+        }
+    })
+}
+
+/// Detects `Result<…>`, `tide::Result`, `std::io::Result<…>` and the like by the last path segment
+fn is_result(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == "Result")
+            .unwrap_or_default(),
+        Type::Group(group) => is_result(&group.elem),
+        Type::Paren(paren) => is_result(&paren.elem),
+        _ => false,
+    }
+}
@@ -0,0 +1,314 @@
+//! One-call setup of the OpenTelemetry pipeline from the standard `OTEL_*` environment variables
+
+use kv_log_macro as log;
+use opentelemetry::{
+    global,
+    sdk::{
+        propagation::TextMapCompositePropagator,
+        trace::{self, Sampler, TracerProvider},
+        Resource,
+    },
+    trace::TraceError,
+};
+use std::env;
+
+#[cfg(feature = "metrics")]
+use crate::{middlewares::metrics::build_exporter_and_init_meter, MetricsConfig, OpenTelemetryMetricsMiddleware};
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::noop::NoopMeterProvider;
+#[cfg(feature = "metrics")]
+use opentelemetry_prometheus::PrometheusExporter;
+
+const OTEL_TRACES_SAMPLER: &str = "OTEL_TRACES_SAMPLER";
+const OTEL_TRACES_SAMPLER_ARG: &str = "OTEL_TRACES_SAMPLER_ARG";
+const OTEL_PROPAGATORS: &str = "OTEL_PROPAGATORS";
+const OTEL_TRACES_EXPORTER: &str = "OTEL_TRACES_EXPORTER";
+#[cfg(feature = "metrics")]
+const OTEL_METRICS_EXPORTER: &str = "OTEL_METRICS_EXPORTER";
+
+const DEFAULT_PROPAGATORS: &str = "tracecontext,baggage";
+const DEFAULT_EXPORTER: &str = "jaeger";
+#[cfg(feature = "metrics")]
+const DEFAULT_METRICS_EXPORTER: &str = "prometheus";
+
+/**
+Installs the global tracer provider, meter provider (with the `metrics` feature) and text map propagator,
+configured by the standard `OTEL_*` environment variables.
+
+The returned guard flushes and shuts down the tracer provider and uninstalls the meter provider when dropped,
+so keep it alive until your application ends.
+The metrics of the installed meter provider are served by the middleware of [TelemetryGuard::metrics_middleware].
+
+| variable | supported values | default |
+| :------- | :--------------- | :------ |
+| `OTEL_SERVICE_NAME` | any | `unknown_service` |
+| `OTEL_RESOURCE_ATTRIBUTES` | `key1=value1,key2=value2` | |
+| `OTEL_TRACES_SAMPLER` | `always_on`, `always_off`, `traceidratio`, `parentbased_always_on`, `parentbased_always_off`, `parentbased_traceidratio` | `parentbased_always_on` |
+| `OTEL_TRACES_SAMPLER_ARG` | ratio in `[0, 1]` for the `*traceidratio` samplers, invalid values are logged | `1.0` |
+| `OTEL_PROPAGATORS` | comma separated list of `tracecontext`, `baggage`, `jaeger`, `none` and the names enabled by the `propagator-*` features (see [crate::propagation::by_name]) | `tracecontext,baggage` |
+| `OTEL_TRACES_EXPORTER` | `jaeger`, `none` | `jaeger` |
+| `OTEL_EXPORTER_JAEGER_AGENT_HOST`, `OTEL_EXPORTER_JAEGER_AGENT_PORT` | see [opentelemetry_jaeger] | `localhost`, `6831` |
+| `OTEL_METRICS_EXPORTER` (with the `metrics` feature) | `prometheus`, `none` | `prometheus` |
+
+Unsupported values are logged and ignored.
+Besides the variables above, the resource contains the attributes of the detectors in [crate::resource].
+
+# Examples
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let telemetry = opentelemetry_tide::bootstrap::init_from_env()?;
+
+    let mut app = tide::new();
+    app.with_tracing_middleware(opentelemetry::global::tracer("my-service"));
+    if let Some(metrics) = telemetry.metrics_middleware() {
+        app.with(metrics);
+    }
+    app.at("/").get(|_| async { Ok("Hello!") });
+    app.listen("0.0.0.0:3000").await?;
+    Ok(())
+}
+```
+*/
+pub fn init_from_env() -> Result<TelemetryGuard, TraceError> {
+    init_from_env_with_resource(crate::resource::detect(None, None))
+}
+
+/**
+Like [`init_from_env`], but with the given resource
+
+Use it together with [`detect_resource!`](crate::detect_resource)
+to get the name and version of your crate as the fallback service name and version:
+
+```rust,no_run
+let telemetry = opentelemetry_tide::bootstrap::init_from_env_with_resource(opentelemetry_tide::detect_resource!());
+```
+*/
+pub fn init_from_env_with_resource(resource: Resource) -> Result<TelemetryGuard, TraceError> {
+    let config = trace::config()
+        .with_sampler(sampler_from_env())
+        .with_resource(resource.clone());
+
+    let exporter = env::var(OTEL_TRACES_EXPORTER).unwrap_or_else(|_| DEFAULT_EXPORTER.to_owned());
+    match exporter.trim() {
+        "none" => {
+            let provider = TracerProvider::builder().with_config(config).build();
+            let _ = global::set_tracer_provider(provider);
+        }
+        other => {
+            if other != DEFAULT_EXPORTER {
+                log::warn!("Unsupported traces exporter {:?}, falling back to jaeger", other);
+            }
+            let _ = opentelemetry_jaeger::new_pipeline()
+                .with_trace_config(config)
+                .install_batch(opentelemetry::runtime::AsyncStd)?;
+        }
+    }
+
+    global::set_text_map_propagator(propagator_from_env());
+
+    Ok(TelemetryGuard {
+        #[cfg(feature = "metrics")]
+        metrics_exporter: metrics_exporter_from_env(&resource),
+        resource,
+    })
+}
+
+/// Keeps the installed telemetry pipeline alive; flushes and shuts it down on drop
+#[derive(Debug)]
+pub struct TelemetryGuard {
+    resource: Resource,
+    #[cfg(feature = "metrics")]
+    metrics_exporter: Option<PrometheusExporter>,
+}
+
+impl TelemetryGuard {
+    /// The resource detected from the environment, as used by the tracer provider
+    pub fn resource(&self) -> &Resource {
+        &self.resource
+    }
+
+    /// A metrics configuration with the detected resource,
+    /// so the metrics carry the same identity as the traces
+    ///
+    /// A middleware created from it installs a meter provider of its own, replacing the one of the guard;
+    /// use [TelemetryGuard::metrics_middleware] to serve the metrics of the guard instead.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_config(&self) -> MetricsConfig {
+        metrics_config(&self.resource)
+    }
+
+    /// A metrics middleware recording into and serving the meter provider installed by the guard;
+    /// `None` if `OTEL_METRICS_EXPORTER` is `none`
+    #[cfg(feature = "metrics")]
+    pub fn metrics_middleware(&self) -> Option<OpenTelemetryMetricsMiddleware> {
+        let exporter = self.metrics_exporter.clone()?;
+        Some(OpenTelemetryMetricsMiddleware::with_exporter(
+            self.metrics_config(),
+            exporter,
+        ))
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        global::force_flush_tracer_provider();
+        global::shutdown_tracer_provider();
+
+        // prometheus pulls the metrics, so there is nothing to flush;
+        // instruments created afterwards record into the void instead of the exporter of the guard
+        #[cfg(feature = "metrics")]
+        if self.metrics_exporter.take().is_some() {
+            global::set_meter_provider(NoopMeterProvider::new());
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn metrics_exporter_from_env(resource: &Resource) -> Option<PrometheusExporter> {
+    let exporter = env::var(OTEL_METRICS_EXPORTER).unwrap_or_else(|_| DEFAULT_METRICS_EXPORTER.to_owned());
+    match exporter.trim() {
+        "none" => None,
+        other => {
+            if other != DEFAULT_METRICS_EXPORTER {
+                log::warn!("Unsupported metrics exporter {:?}, falling back to prometheus", other);
+            }
+            Some(build_exporter_and_init_meter(&metrics_config(resource)))
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn metrics_config(resource: &Resource) -> MetricsConfig {
+    MetricsConfig {
+        resource: Some(resource.clone()),
+        ..Default::default()
+    }
+}
+
+fn sampler_from_env() -> Sampler {
+    let sampler = env::var(OTEL_TRACES_SAMPLER).ok();
+    let arg = env::var(OTEL_TRACES_SAMPLER_ARG).ok();
+    sampler_from(sampler.as_deref(), arg.as_deref())
+}
+
+fn sampler_from(sampler: Option<&str>, arg: Option<&str>) -> Sampler {
+    let ratio = || match arg.map(str::trim) {
+        None | Some("") => 1.0,
+        Some(arg) => match arg.parse::<f64>() {
+            Ok(ratio) if (0.0..=1.0).contains(&ratio) => ratio,
+            _ => {
+                log::warn!("Unsupported traces sampler argument {:?}, falling back to 1.0", arg);
+                1.0
+            }
+        },
+    };
+    match sampler.unwrap_or_default().trim() {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()),
+        "parentbased_always_off" => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        "parentbased_traceidratio" => Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio()))),
+        "" | "parentbased_always_on" => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+        other => {
+            log::warn!(
+                "Unsupported traces sampler {:?}, falling back to parentbased_always_on",
+                other
+            );
+            Sampler::ParentBased(Box::new(Sampler::AlwaysOn))
+        }
+    }
+}
+
+fn propagator_from_env() -> TextMapCompositePropagator {
+    let names = env::var(OTEL_PROPAGATORS).unwrap_or_else(|_| DEFAULT_PROPAGATORS.to_owned());
+    propagator_from(&names)
+}
+
+fn propagator_from(names: &str) -> TextMapCompositePropagator {
+    let mut propagators = Vec::new();
+    for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match (name, crate::propagation::by_name(name)) {
+            ("none", _) => propagators.clear(),
+            (_, Some(propagator)) => propagators.push(propagator),
+            (other, None) => log::warn!("Unsupported propagator {:?}, ignoring it", other),
+        }
+    }
+    TextMapCompositePropagator::new(propagators)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::propagation::TextMapPropagator;
+
+    fn sampler(sampler: Option<&str>, arg: Option<&str>) -> String {
+        format!("{:?}", sampler_from(sampler, arg))
+    }
+
+    fn fields(names: &str) -> Vec<String> {
+        let mut fields: Vec<String> = propagator_from(names).fields().map(str::to_owned).collect();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn samplers_are_parsed_by_name() {
+        assert_eq!(sampler(Some("always_on"), None), "AlwaysOn");
+        assert_eq!(sampler(Some("always_off"), None), "AlwaysOff");
+        assert_eq!(sampler(Some("traceidratio"), Some("0.25")), "TraceIdRatioBased(0.25)");
+        assert_eq!(
+            sampler(Some(" parentbased_always_off "), None),
+            "ParentBased(AlwaysOff)"
+        );
+        assert_eq!(
+            sampler(Some("parentbased_traceidratio"), Some(" 0.5 ")),
+            "ParentBased(TraceIdRatioBased(0.5))"
+        );
+        assert_eq!(sampler(Some("parentbased_always_on"), None), "ParentBased(AlwaysOn)");
+    }
+
+    #[test]
+    fn missing_or_unsupported_sampler_falls_back_to_parentbased_always_on() {
+        assert_eq!(sampler(None, None), "ParentBased(AlwaysOn)");
+        assert_eq!(sampler(Some(""), None), "ParentBased(AlwaysOn)");
+        assert_eq!(sampler(Some("jaeger_remote"), None), "ParentBased(AlwaysOn)");
+    }
+
+    #[test]
+    fn missing_ratio_defaults_to_one() {
+        assert_eq!(sampler(Some("traceidratio"), None), "TraceIdRatioBased(1.0)");
+        assert_eq!(sampler(Some("traceidratio"), Some("")), "TraceIdRatioBased(1.0)");
+    }
+
+    #[test]
+    fn invalid_ratio_falls_back_to_one() {
+        for arg in ["half", "1.5", "-0.1", "NaN"] {
+            assert_eq!(
+                sampler(Some("traceidratio"), Some(arg)),
+                "TraceIdRatioBased(1.0)",
+                "{}",
+                arg
+            );
+        }
+    }
+
+    #[test]
+    fn propagators_are_parsed_by_name() {
+        assert_eq!(
+            fields(DEFAULT_PROPAGATORS),
+            vec!["baggage", "traceparent", "tracestate"]
+        );
+        assert_eq!(fields(" tracecontext "), vec!["traceparent", "tracestate"]);
+    }
+
+    #[test]
+    fn none_and_unsupported_propagators_are_dropped() {
+        assert!(fields("none").is_empty());
+        assert_eq!(fields("tracecontext,none,baggage"), vec!["baggage"]);
+        assert_eq!(fields("unknown,baggage,"), vec!["baggage"]);
+    }
+}
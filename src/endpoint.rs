@@ -0,0 +1,171 @@
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    trace::{FutureExt, SpanKind, StatusCode, TraceContextExt, Tracer, TracerProvider},
+    Context,
+};
+use opentelemetry_semantic_conventions::trace;
+use std::{borrow::Cow, fmt};
+use tide::{http::Method, Endpoint, Request, Result, Route};
+
+/**
+Wraps an endpoint, so every call of it gets its own internal child span of the server span.
+
+The span is named after the handler (its type name); use [TracedEndpoint::named] for a custom name.
+
+# Examples
+
+```rust,no_run
+async fn hello(_req: tide::Request<()>) -> tide::Result {
+    Ok("Hello!".into())
+}
+
+let mut app = tide::new();
+app.with(opentelemetry_tide::OpenTelemetryTracingMiddleware::default());
+app.at("/").get(opentelemetry_tide::traced(hello));
+```
+*/
+pub fn traced<E>(endpoint: E) -> TracedEndpoint<E> {
+    TracedEndpoint::named(short_type_name::<E>(), endpoint)
+}
+
+/// An endpoint wrapper creating a span for the handler; see [traced] for details
+pub struct TracedEndpoint<E> {
+    name: Cow<'static, str>,
+    route: Option<String>,
+    tracer: BoxedTracer,
+    endpoint: E,
+}
+
+impl<E> TracedEndpoint<E> {
+    /// Wraps an endpoint with a span of the given name
+    pub fn named<N: Into<Cow<'static, str>>>(name: N, endpoint: E) -> Self {
+        let tracer = global::tracer_provider().versioned_tracer(crate::CRATE_NAME, Some(crate::VERSION), None);
+        Self {
+            name: name.into(),
+            route: None,
+            tracer,
+            endpoint,
+        }
+    }
+
+    /// Sets the route template of the endpoint, which gets added as `http.route` to the span
+    pub fn with_route<R: Into<String>>(mut self, route: R) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+}
+
+impl<E> fmt::Debug for TracedEndpoint<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracedEndpoint")
+            .field("name", &self.name)
+            .field("route", &self.route)
+            .finish()
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State, E> Endpoint<State> for TracedEndpoint<E>
+where
+    State: Clone + Send + Sync + 'static,
+    E: Endpoint<State>,
+{
+    async fn call(&self, req: Request<State>) -> Result {
+        let mut attributes = Vec::with_capacity(2);
+        attributes.push(trace::CODE_FUNCTION.string(self.name.clone()));
+        if let Some(route) = &self.route {
+            attributes.push(trace::HTTP_ROUTE.string(route.clone()));
+        }
+
+        // the parent is the server span of the tracing middleware, if present
+        let span = self
+            .tracer
+            .span_builder(self.name.clone())
+            .with_kind(SpanKind::Internal)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &Context::current());
+        let cx = Context::current_with_span(span);
+
+        let result = self.endpoint.call(req).with_context(cx.clone()).await;
+
+        let span = cx.span();
+        match &result {
+            Ok(res) => {
+                span.set_attribute(trace::HTTP_STATUS_CODE.i64(u16::from(res.status()).into()));
+                if res.status().is_server_error() {
+                    span.set_status(StatusCode::Error, "".to_string());
+                }
+            }
+            Err(err) => {
+                span.set_attribute(trace::HTTP_STATUS_CODE.i64(u16::from(err.status()).into()));
+                span.set_status(StatusCode::Error, err.to_string());
+            }
+        }
+        span.end();
+        result
+    }
+}
+
+/**
+This extension trait provides methods for registering traced endpoints on a route.
+
+The spans are named after the method and the route template, like `GET /users/:id`.
+
+# Examples
+
+```rust,no_run
+use opentelemetry_tide::TracedRouteExt;
+
+let mut app = tide::new();
+app.with(opentelemetry_tide::OpenTelemetryTracingMiddleware::default());
+app.at("/users/:id").get_traced(|_| async { Ok("Traced!") });
+```
+*/
+pub trait TracedRouteExt<State: Clone + Send + Sync + 'static> {
+    /// Adds a traced endpoint for the given method; see [tide::Route::method]
+    fn method_traced(&mut self, method: Method, endpoint: impl Endpoint<State>) -> &mut Self;
+
+    /// Adds a traced endpoint for GET requests
+    fn get_traced(&mut self, endpoint: impl Endpoint<State>) -> &mut Self {
+        self.method_traced(Method::Get, endpoint)
+    }
+
+    /// Adds a traced endpoint for POST requests
+    fn post_traced(&mut self, endpoint: impl Endpoint<State>) -> &mut Self {
+        self.method_traced(Method::Post, endpoint)
+    }
+
+    /// Adds a traced endpoint for PUT requests
+    fn put_traced(&mut self, endpoint: impl Endpoint<State>) -> &mut Self {
+        self.method_traced(Method::Put, endpoint)
+    }
+
+    /// Adds a traced endpoint for PATCH requests
+    fn patch_traced(&mut self, endpoint: impl Endpoint<State>) -> &mut Self {
+        self.method_traced(Method::Patch, endpoint)
+    }
+
+    /// Adds a traced endpoint for DELETE requests
+    fn delete_traced(&mut self, endpoint: impl Endpoint<State>) -> &mut Self {
+        self.method_traced(Method::Delete, endpoint)
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> TracedRouteExt<State> for Route<'_, State> {
+    fn method_traced(&mut self, method: Method, endpoint: impl Endpoint<State>) -> &mut Self {
+        let route = self.path().to_owned();
+        let endpoint = TracedEndpoint::named(format!("{} {}", method, route), endpoint).with_route(route);
+        self.method(method, endpoint)
+    }
+}
+
+/// `std::any::type_name` without the module path, e.g. `my_app::handlers::hello` becomes `hello`
+fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    // closures are named like `my_app::main::{{closure}}`, keep the enclosing function then
+    let path = name.trim_end_matches("::{{closure}}");
+    match path.rsplit("::").next() {
+        Some(short) if !short.is_empty() => short,
+        _ => name,
+    }
+}
@@ -0,0 +1,108 @@
+// runtime support for the `#[instrument]` macro; re-exported via `__private`, not a public API
+
+use opentelemetry::{
+    global,
+    trace::{FutureExt, SpanKind, SpanRef, StatusCode, TraceContextExt, Tracer, TracerProvider},
+    Context,
+};
+use opentelemetry_semantic_conventions::trace;
+use std::{fmt::Display, future::Future};
+
+pub use opentelemetry::KeyValue;
+
+fn start(
+    name: &'static str,
+    function: &'static str,
+    namespace: &'static str,
+    mut attributes: Vec<KeyValue>,
+) -> Context {
+    let tracer = global::tracer_provider().versioned_tracer(crate::CRATE_NAME, Some(crate::VERSION), None);
+    attributes.push(trace::CODE_FUNCTION.string(function));
+    attributes.push(trace::CODE_NAMESPACE.string(namespace));
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Internal)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+    Context::current_with_span(span)
+}
+
+fn record_result<T, E: Display>(span: &SpanRef<'_>, result: &Result<T, E>) {
+    if let Err(err) = result {
+        span.set_status(StatusCode::Error, err.to_string());
+    }
+}
+
+/// Runs the future within a new span
+pub async fn in_span<F: Future>(
+    name: &'static str,
+    function: &'static str,
+    namespace: &'static str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> F::Output {
+    let cx = start(name, function, namespace, attributes);
+    let output = future.with_context(cx.clone()).await;
+    cx.span().end();
+    output
+}
+
+/// Runs the future within a new span, marking the span as error if it resolves to an `Err`
+pub async fn in_span_result<F, T, E>(
+    name: &'static str,
+    function: &'static str,
+    namespace: &'static str,
+    attributes: Vec<KeyValue>,
+    future: F,
+) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let cx = start(name, function, namespace, attributes);
+    let result = future.with_context(cx.clone()).await;
+    let span = cx.span();
+    record_result(&span, &result);
+    span.end();
+    result
+}
+
+/// Runs the function within a new span
+pub fn in_span_sync<F: FnOnce() -> T, T>(
+    name: &'static str,
+    function: &'static str,
+    namespace: &'static str,
+    attributes: Vec<KeyValue>,
+    f: F,
+) -> T {
+    let cx = start(name, function, namespace, attributes);
+    let output = {
+        let _guard = cx.clone().attach();
+        f()
+    };
+    cx.span().end();
+    output
+}
+
+/// Runs the function within a new span, marking the span as error if it returns an `Err`
+pub fn in_span_sync_result<F, T, E>(
+    name: &'static str,
+    function: &'static str,
+    namespace: &'static str,
+    attributes: Vec<KeyValue>,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: Display,
+{
+    let cx = start(name, function, namespace, attributes);
+    let result = {
+        let _guard = cx.clone().attach();
+        f()
+    };
+    let span = cx.span();
+    record_result(&span, &result);
+    span.end();
+    result
+}
@@ -13,19 +13,92 @@
 #![deny(unused_results)]
 #![warn(clippy::expect_used)]
 
+#[cfg(any(feature = "trace", doc))]
 use opentelemetry::global::BoxedTracer;
+#[cfg(feature = "trace")]
 const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+#[cfg(feature = "trace")]
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[cfg(feature = "bootstrap")]
+pub mod bootstrap;
+#[cfg(any(feature = "trace", doc))]
+mod endpoint;
+#[cfg(feature = "macros")]
+mod instrument;
+#[cfg(feature = "trace")]
+pub mod logging;
 mod middlewares;
+#[cfg(feature = "trace")]
+pub mod propagation;
+pub mod resource;
+#[cfg(feature = "shutdown")]
+pub mod shutdown;
+#[cfg(feature = "trace")]
+pub mod tail_sampling;
+
+#[cfg(feature = "macros")]
+pub use opentelemetry_tide_macros::instrument;
+
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::instrument::{in_span, in_span_result, in_span_sync, in_span_sync_result, KeyValue};
+}
+
+#[cfg(any(feature = "trace", doc))]
+pub use endpoint::{traced, TracedEndpoint, TracedRouteExt};
+
+#[cfg(any(feature = "trace", doc))]
+pub use middlewares::tracing::{OpenTelemetryTracingMiddleware, TracingConfig};
+
+#[cfg(any(feature = "trace", doc))]
+pub use middlewares::sampling::{DebugTraceConfig, RouteSampling, SamplingRule};
+
+#[cfg(any(feature = "trace", doc))]
+pub use middlewares::access_log::{AccessLogConfig, AccessLogMiddleware};
 
 #[cfg(any(feature = "trace", doc))]
-pub use middlewares::tracing::OpenTelemetryTracingMiddleware;
+pub use middlewares::request_id::{RequestId, RequestIdConfig, RequestIdGenerator, RequestIdMiddleware};
+
+#[cfg(any(feature = "trace", doc))]
+pub use middlewares::error_response::{ErrorFormat, ErrorResponseConfig, ErrorResponseMiddleware};
 
 #[cfg(any(feature = "metrics", doc))]
 pub use middlewares::metrics::{MetricsConfig, OpenTelemetryMetricsMiddleware};
 
-/// this extension trait provides convenience methods for attaching middlewares of this crate
+#[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+pub use middlewares::combined::OpenTelemetryMiddleware;
+
+/**
+this extension trait provides convenience methods for attaching middlewares of this crate
+
+It is implemented for both `tide::Server` and `tide::Route`.
+When attached to a route, the middlewares only apply to the endpoints and nested apps registered on it afterwards,
+and the route path is reported as the route template.
+The metrics middlewares attached to routes do not serve the metrics scraping route,
+which is not among the route's endpoints; they record into the installed meter provider instead
+(installing one only if there is none yet, so their exporter settings only apply then).
+To serve them, attach a metrics middleware to the app first, which installs the meter provider
+and serves the scraping route (besides recording all requests of the app).
+Requests passed on to a nested app are reported with a `*` wildcard segment appended to the route path,
+like for the `/api` nesting route below.
+
+Tide strips the nesting prefix off the path before a nested app sees the request;
+the middlewares of this crate pass it on, so middlewares attached within the nested app
+(like to `/users/:id` below, reported as `/api/users/:id`) prepend it to their route,
+as long as any middleware of this crate is attached at the nesting route or to the outer app.
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+let mut api = tide::new();
+api.at("/users/:id").get(|_| async { Ok("Traced!") });
+
+let mut app = tide::new();
+app.at("/api").with_default_middlewares().nest(api);
+```
+*/
 pub trait TideExt<S> {
     /**
     Attaches tracing middleware with provided tracer.
@@ -86,6 +159,26 @@ pub trait TideExt<S> {
     fn with_default_middlewares(&mut self) -> &mut Self
     where
         S: Clone + Send + Sync + 'static;
+
+    /**
+    Attaches the combined tracing and metrics middleware with provided tracer and MetricsConfig.
+
+    See [OpenTelemetryMiddleware::new] for details.
+    */
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_combined_middleware(&mut self, tracer: BoxedTracer, config: MetricsConfig) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static;
+
+    /**
+    Attaches the combined tracing and metrics middleware with its defaults.
+
+    See [OpenTelemetryMiddleware::default] for details.
+    */
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_default_combined_middleware(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static;
 }
 
 impl<S> TideExt<S> for tide::Server<S> {
@@ -138,4 +231,103 @@ impl<S> TideExt<S> for tide::Server<S> {
         self.with(OpenTelemetryTracingMiddleware::default())
             .with(OpenTelemetryMetricsMiddleware::default())
     }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_combined_middleware(&mut self, tracer: BoxedTracer, config: MetricsConfig) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.with(OpenTelemetryMiddleware::new(tracer, config))
+    }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_default_combined_middleware(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.with(OpenTelemetryMiddleware::default())
+    }
+}
+
+impl<S> TideExt<S> for tide::Route<'_, S> {
+    #[cfg(any(feature = "trace", doc))]
+    fn with_tracing_middleware(&mut self, tracer: BoxedTracer) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        self.with(OpenTelemetryTracingMiddleware::new(tracer).with_route_template(route_template))
+    }
+
+    #[cfg(any(feature = "trace", doc))]
+    fn with_default_tracing_middleware(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        self.with(OpenTelemetryTracingMiddleware::default().with_route_template(route_template))
+    }
+
+    #[cfg(any(feature = "metrics", doc))]
+    fn with_metrics_middleware(&mut self, config: MetricsConfig) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        self.with(OpenTelemetryMetricsMiddleware::on_installed_meter(config).with_route_template(route_template))
+    }
+
+    #[cfg(any(feature = "metrics", doc))]
+    fn with_default_metrics_middleware(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        self.with(
+            OpenTelemetryMetricsMiddleware::on_installed_meter(MetricsConfig::default())
+                .with_route_template(route_template),
+        )
+    }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_middlewares(&mut self, tracer: BoxedTracer, config: MetricsConfig) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.with_tracing_middleware(tracer).with_metrics_middleware(config)
+    }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_default_middlewares(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.with_default_tracing_middleware().with_default_metrics_middleware()
+    }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_combined_middleware(&mut self, tracer: BoxedTracer, config: MetricsConfig) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        let middleware = OpenTelemetryMiddleware::from_parts(
+            OpenTelemetryTracingMiddleware::new(tracer),
+            OpenTelemetryMetricsMiddleware::on_installed_meter(config),
+        );
+        self.with(middleware.with_route_template(route_template))
+    }
+
+    #[cfg(any(all(feature = "trace", feature = "metrics"), doc))]
+    fn with_default_combined_middleware(&mut self) -> &mut Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let route_template = self.path().to_owned();
+        let middleware = OpenTelemetryMiddleware::from_parts(
+            OpenTelemetryTracingMiddleware::default(),
+            OpenTelemetryMetricsMiddleware::on_installed_meter(MetricsConfig::default()),
+        );
+        self.with(middleware.with_route_template(route_template))
+    }
 }
@@ -0,0 +1,210 @@
+//! Trace correlation for the logs emitted during a request, and logs as span events
+
+use log::{
+    kv::{self, Key, Source, Value, VisitSource},
+    Level, Log, Metadata, Record, SetLoggerError,
+};
+use opentelemetry::{
+    trace::{SpanContext, TraceContextExt},
+    Context, KeyValue,
+};
+
+const TRACE_ID: &str = "trace_id";
+const SPAN_ID: &str = "span_id";
+const TRACE_FLAGS: &str = "trace_flags";
+const LEVEL: &str = "level";
+const TARGET: &str = "target";
+
+/**
+A `log::Log` wrapper which adds `trace_id`, `span_id` and `trace_flags` key-values to each record,
+taken from the current OpenTelemetry context, before delegating to the wrapped logger
+
+Within the tracing middlewares the current context is the one of the server span,
+so every record logged by handlers and inner middlewares (via `log` or `kv_log_macro`) can be joined with the trace.
+Records outside of a valid span context, or which already carry a `trace_id`, are passed through untouched.
+
+# Examples
+
+```rust,no_run
+# struct MyLogger;
+# impl log::Log for MyLogger {
+#     fn enabled(&self, _: &log::Metadata) -> bool { true }
+#     fn log(&self, _: &log::Record) {}
+#     fn flush(&self) {}
+# }
+opentelemetry_tide::logging::TraceContextLogger::new(MyLogger)
+    .install(log::LevelFilter::Info)
+    .expect("no other logger installed");
+```
+*/
+#[derive(Debug)]
+pub struct TraceContextLogger<L> {
+    inner: L,
+}
+
+impl<L: Log + 'static> TraceContextLogger<L> {
+    /// Wraps the given logger
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+
+    /// Installs the wrapped logger as the global logger with the given maximum level
+    pub fn install(self, max_level: log::LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl<L: Log> Log for TraceContextLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let cx = Context::current();
+        let span_context = cx.span().span_context().clone();
+        if !span_context.is_valid() || record.key_values().get(Key::from_str(TRACE_ID)).is_some() {
+            self.inner.log(record);
+            return;
+        }
+
+        let key_values = WithSpanContext::new(record.key_values(), &span_context);
+        self.inner.log(&record.to_builder().key_values(&key_values).build());
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// The key-values of a record, followed by the ones of a span context
+struct WithSpanContext<'a> {
+    inner: &'a dyn Source,
+    trace_id: String,
+    span_id: String,
+    trace_flags: String,
+}
+
+impl<'a> WithSpanContext<'a> {
+    fn new(inner: &'a dyn Source, span_context: &SpanContext) -> Self {
+        Self {
+            inner,
+            trace_id: span_context.trace_id().to_string(),
+            span_id: span_context.span_id().to_string(),
+            trace_flags: format!("{:02x}", span_context.trace_flags()),
+        }
+    }
+}
+
+impl Source for WithSpanContext<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        self.inner.visit(visitor)?;
+        visitor.visit_pair(Key::from_str(TRACE_ID), Value::from(self.trace_id.as_str()))?;
+        visitor.visit_pair(Key::from_str(SPAN_ID), Value::from(self.span_id.as_str()))?;
+        visitor.visit_pair(Key::from_str(TRACE_FLAGS), Value::from(self.trace_flags.as_str()))
+    }
+}
+
+/**
+A `log::Log` wrapper which also records each log record as an event on the current span,
+so the trace view shows the logs of a request inline
+
+The event is named after the log message and carries the `level`, the `target` and the key-values of the record.
+Only records at or above the minimum level (default: `Info`) become events, and only if the current span is recording;
+all records are still passed on to the wrapped logger.
+Mind that the global `log::max_level()` filters records before any logger sees them.
+
+# Examples
+
+```rust,no_run
+# struct MyLogger;
+# impl log::Log for MyLogger {
+#     fn enabled(&self, _: &log::Metadata) -> bool { true }
+#     fn log(&self, _: &log::Record) {}
+#     fn flush(&self) {}
+# }
+use opentelemetry_tide::logging::{SpanEventLogger, TraceContextLogger};
+
+SpanEventLogger::new(TraceContextLogger::new(MyLogger))
+    .with_min_level(log::Level::Debug)
+    .install(log::LevelFilter::Debug)
+    .expect("no other logger installed");
+```
+*/
+#[derive(Debug)]
+pub struct SpanEventLogger<L> {
+    inner: L,
+    min_level: Level,
+}
+
+impl<L: Log + 'static> SpanEventLogger<L> {
+    /// Wraps the given logger, recording span events for records at `Info` level and above
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            min_level: Level::Info,
+        }
+    }
+
+    /// Sets the minimum level of the records which become span events
+    pub fn with_min_level(mut self, min_level: Level) -> Self {
+        self.min_level = min_level;
+        self
+    }
+
+    /// Installs the wrapped logger as the global logger with the given maximum level
+    pub fn install(self, max_level: log::LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl<L: Log> Log for SpanEventLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.min_level || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if record.level() <= self.min_level {
+            let cx = Context::current();
+            let span = cx.span();
+            if span.is_recording() {
+                let mut attributes = vec![
+                    KeyValue::new(LEVEL, record.level().as_str()),
+                    KeyValue::new(TARGET, record.target().to_owned()),
+                ];
+                let _ = record.key_values().visit(&mut AttributeCollector(&mut attributes));
+                span.add_event(record.args().to_string(), attributes);
+            }
+        }
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Turns log key-values into span attributes, keeping booleans and numbers as such
+struct AttributeCollector<'a>(&'a mut Vec<KeyValue>);
+
+impl<'kvs> VisitSource<'kvs> for AttributeCollector<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let key = key.as_str().to_owned();
+        let attribute = if let Some(value) = value.to_bool() {
+            KeyValue::new(key, value)
+        } else if let Some(value) = value.to_i64() {
+            KeyValue::new(key, value)
+        } else if let Some(value) = value.to_f64() {
+            KeyValue::new(key, value)
+        } else {
+            KeyValue::new(key, value.to_string())
+        };
+        self.0.push(attribute);
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use super::body::{has_body, ObservedBody};
+use super::request_id::RequestId;
+use super::route::ResolvedRoute;
+use kv_log_macro::Level;
+use log::kv::ToValue;
+use opentelemetry::{trace::TraceContextExt, Context};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tide::{http::headers::USER_AGENT, Middleware, Next, Request, Response, Result, StatusCode};
+
+/**
+Configuration for the access log middleware
+
+Every field flag toggles one key of the emitted log record.
+Unless you need specific values, [AccessLogConfig::default()] logs all of them.
+*/
+#[derive(Debug, Clone)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct AccessLogConfig {
+    /// Log the request method as `method`
+    pub method: bool,
+    /// Log the route (or the path, if the route is unknown) as `route`
+    pub route: bool,
+    /// Log the response status code as `status`
+    pub status: bool,
+    /// Log the request duration in milliseconds as `duration_ms`
+    pub duration: bool,
+    /// Log the size of the response body as `bytes`
+    pub bytes: bool,
+    /// Log the client IP address (respecting `Forwarded` and `X-Forwarded-For` headers) as `client_ip`
+    pub client_ip: bool,
+    /// Log the `User-Agent` header as `user_agent`
+    pub user_agent: bool,
+    /// Log the trace id of the current span as `trace_id`
+    pub trace_id: bool,
+    /// Log the span id of the current span as `span_id`
+    pub span_id: bool,
+    /// Log the request id set by the [crate::RequestIdMiddleware] as `request_id`
+    pub request_id: bool,
+    /// Log level for informational, successful and redirection responses (1xx-3xx)
+    pub success_level: Level,
+    /// Log level for client error responses (4xx)
+    pub client_error_level: Level,
+    /// Log level for server error responses (5xx)
+    pub server_error_level: Level,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            method: true,
+            route: true,
+            status: true,
+            duration: true,
+            bytes: true,
+            client_ip: true,
+            user_agent: true,
+            trace_id: true,
+            span_id: true,
+            request_id: true,
+            success_level: Level::Info,
+            client_error_level: Level::Warn,
+            server_error_level: Level::Error,
+        }
+    }
+}
+
+impl AccessLogConfig {
+    fn level(&self, status: StatusCode) -> Level {
+        if status.is_server_error() {
+            self.server_error_level
+        } else if status.is_client_error() {
+            self.client_error_level
+        } else {
+            self.success_level
+        }
+    }
+}
+
+/**
+Access log middleware, emitting one structured log record per request which joins with the traces on `trace_id`
+
+The record is emitted when the response was produced, or, for bodies of unknown length (streams),
+when the body has been fully sent, so that `bytes` and `duration_ms` cover the whole response.
+
+Register it **after** the tracing middleware, so that the server span is the current span:
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+let mut app = tide::new();
+app.with_default_middlewares();
+app.with(opentelemetry_tide::AccessLogMiddleware::new());
+app.at("/").get(|_| async { Ok("Logged!") });
+```
+*/
+#[derive(Debug, Default)]
+pub struct AccessLogMiddleware {
+    config: AccessLogConfig,
+    route_template: Option<String>,
+}
+
+impl AccessLogMiddleware {
+    /// Instantiate the middleware with the default configuration, logging all fields
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instantiate the middleware with a custom configuration
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// let config = opentelemetry_tide::AccessLogConfig {
+    ///     user_agent: false,
+    ///     success_level: kv_log_macro::Level::Debug,
+    ///     ..Default::default()
+    /// };
+    /// app.with(opentelemetry_tide::AccessLogMiddleware::new_with_config(config));
+    /// ```
+    pub fn new_with_config(config: AccessLogConfig) -> Self {
+        Self {
+            config,
+            route_template: None,
+        }
+    }
+
+    /// Logs the given route template instead of the request path,
+    /// useful when the middleware is attached to a `tide::Route` with parameters
+    pub fn with_route(mut self, route: impl Into<String>) -> Self {
+        self.route_template = Some(route.into());
+        self
+    }
+}
+
+/// Everything known about the request before the handler ran
+struct AccessLogEntry {
+    config: AccessLogConfig,
+    // `METHOD route` for the log message, independent of the logged fields
+    request_line: String,
+    method: Option<String>,
+    route: Option<String>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    request_id: Option<String>,
+    start: Instant,
+}
+
+impl AccessLogEntry {
+    /// Emits the record straight through the logger, unlike the `kv_log_macro` calls elsewhere in this crate:
+    /// the macros take a fixed set of fields and a level known at compile time,
+    /// while both depend on the configuration and the status here
+    fn emit(self, status: StatusCode, bytes: Option<u64>, elapsed: Duration) {
+        let config = &self.config;
+        let status_code = u16::from(status);
+        let duration_ms = elapsed.as_secs_f64() * 1000.0;
+
+        let mut kvs: Vec<(&str, &dyn ToValue)> = Vec::with_capacity(10);
+        if let Some(method) = &self.method {
+            kvs.push(("method", method));
+        }
+        if let Some(route) = &self.route {
+            kvs.push(("route", route));
+        }
+        if config.status {
+            kvs.push(("status", &status_code));
+        }
+        if config.duration {
+            kvs.push(("duration_ms", &duration_ms));
+        }
+        if let Some(bytes) = bytes.as_ref().filter(|_| config.bytes) {
+            kvs.push(("bytes", bytes));
+        }
+        if let Some(client_ip) = &self.client_ip {
+            kvs.push(("client_ip", client_ip));
+        }
+        if let Some(user_agent) = &self.user_agent {
+            kvs.push(("user_agent", user_agent));
+        }
+        if let Some(trace_id) = &self.trace_id {
+            kvs.push(("trace_id", trace_id));
+        }
+        if let Some(span_id) = &self.span_id {
+            kvs.push(("span_id", span_id));
+        }
+        if let Some(request_id) = &self.request_id {
+            kvs.push(("request_id", request_id));
+        }
+
+        let level = config.level(status);
+        if level <= log::max_level() {
+            log::logger().log(
+                &log::Record::builder()
+                    .args(format_args!("{} {}", self.request_line, status_code))
+                    .level(level)
+                    .target(module_path!())
+                    .module_path_static(Some(module_path!()))
+                    .file_static(Some(file!()))
+                    .line(Some(line!()))
+                    .key_values(&kvs.as_slice())
+                    .build(),
+            );
+        }
+    }
+}
+
+fn client_ip<State>(req: &Request<State>) -> Option<String> {
+    req.remote()
+        .and_then(|ipaddr| IpAddr::from_str(ipaddr).ok())
+        .or_else(|| {
+            req.peer_addr()
+                .and_then(|sockaddr| SocketAddr::from_str(sockaddr).ok())
+                .map(|sockaddr| sockaddr.ip())
+        })
+        .map(|ipaddr| ipaddr.to_string())
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for AccessLogMiddleware {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+        let config = &self.config;
+        let cx = Context::current();
+        let span_context = cx.span().span_context().clone();
+        let span_context = Some(span_context).filter(|span_context| span_context.is_valid());
+        let mut resolved = ResolvedRoute::new(&req, self.route_template.as_deref());
+        let route = resolved.route.clone();
+
+        let entry = AccessLogEntry {
+            config: config.clone(),
+            request_line: format!("{} {}", req.method(), route),
+            method: Some(req.method().to_string()).filter(|_| config.method),
+            route: Some(route).filter(|_| config.route),
+            client_ip: client_ip(&req).filter(|_| config.client_ip),
+            user_agent: req
+                .header(USER_AGENT)
+                .map(|value| value.as_str().to_owned())
+                .filter(|_| config.user_agent),
+            trace_id: span_context
+                .as_ref()
+                .filter(|_| config.trace_id)
+                .map(|span_context| span_context.trace_id().to_string()),
+            span_id: span_context
+                .as_ref()
+                .filter(|_| config.span_id)
+                .map(|span_context| span_context.span_id().to_string()),
+            request_id: req
+                .ext::<RequestId>()
+                .filter(|_| config.request_id)
+                .map(|request_id| request_id.as_str().to_owned()),
+            start: Instant::now(),
+        };
+
+        let method = req.method();
+        resolved.pass_on(&mut req);
+        let mut res: Response = next.run(req).await;
+        let status = res.status();
+
+        match res.len() {
+            None if has_body(method, &res) => {
+                let body = res.take_body();
+                res.set_body(ObservedBody::wrap(body, move |stats, _| {
+                    let elapsed = entry.start.elapsed();
+                    entry.emit(status, Some(stats.bytes), elapsed);
+                }));
+            }
+            len => {
+                let elapsed = entry.start.elapsed();
+                entry.emit(status, Some(len.unwrap_or(0) as u64), elapsed);
+            }
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,156 @@
+use futures_util::io::{AsyncBufRead, AsyncRead};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tide::{http::Method, Body, Response};
+
+/// How the stream of an observed body ended
+#[derive(Debug)]
+pub(crate) enum BodyOutcome {
+    /// the body was read until its end
+    Completed,
+    /// reading the body failed with the given error message (only reported by the tracing middleware)
+    Failed(#[cfg_attr(not(feature = "trace"), allow(dead_code))] String),
+    /// the body was dropped before it was fully read, e.g. due to a client disconnect
+    Dropped,
+}
+
+impl BodyOutcome {
+    fn failed(error: &io::Error) -> Self {
+        Self::Failed(error.to_string())
+    }
+}
+
+/// What passed through an observed body
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyStats {
+    /// number of bytes read from the body
+    pub(crate) bytes: u64,
+    /// time from the first read attempt until the end of the stream;
+    /// `None` if the body was never read
+    pub(crate) read_time: Option<Duration>,
+}
+
+type Callback = Box<dyn FnOnce(BodyStats, BodyOutcome) + Send + Sync + 'static>;
+
+/// A body stream wrapper which counts the bytes passing through, measures the time spent reading,
+/// and calls back exactly once, when the stream ended (in whichever way)
+///
+/// No buffering happens in here, reads are passed through to the inner body.
+pub(crate) struct ObservedBody {
+    inner: Body,
+    // the outer body stops reading at a known length, so the end of the stream is never polled then
+    len: Option<u64>,
+    bytes: u64,
+    first_read: Option<Instant>,
+    callback: Option<Callback>,
+}
+
+impl ObservedBody {
+    /// Wraps the body into an observed one, keeping its length and mime type
+    pub(crate) fn wrap<F>(body: Body, callback: F) -> Body
+    where
+        F: FnOnce(BodyStats, BodyOutcome) + Send + Sync + 'static,
+    {
+        let len = body.len();
+        let mime = body.mime().clone();
+        let observed = Self {
+            inner: body,
+            len: len.map(|len| len as u64),
+            bytes: 0,
+            first_read: None,
+            callback: Some(Box::new(callback)),
+        };
+        let mut body = Body::from_reader(observed, len);
+        body.set_mime(mime);
+        body
+    }
+
+    fn started(&mut self) {
+        if self.first_read.is_none() {
+            self.first_read = Some(Instant::now());
+        }
+    }
+
+    fn finish(&mut self, outcome: BodyOutcome) {
+        finish(&mut self.callback, self.bytes, self.first_read, outcome)
+    }
+
+    fn advance(&mut self, amt: usize) {
+        self.bytes += amt as u64;
+        if self.len.map(|len| self.bytes >= len).unwrap_or_default() {
+            self.finish(BodyOutcome::Completed);
+        }
+    }
+}
+
+// a free function, so it can be used while the inner body is still borrowed
+fn finish(callback: &mut Option<Callback>, bytes: u64, first_read: Option<Instant>, outcome: BodyOutcome) {
+    if let Some(callback) = callback.take() {
+        let stats = BodyStats {
+            bytes,
+            read_time: first_read.map(|instant| instant.elapsed()),
+        };
+        callback(stats, outcome);
+    }
+}
+
+/// HEAD requests and empty responses will never have their bodies read by the server,
+/// so there is nothing worth observing
+#[inline]
+pub(crate) fn has_body(method: Method, res: &Response) -> bool {
+    method != Method::Head && res.len() != Some(0)
+}
+
+impl AsyncRead for ObservedBody {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.started();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        match &poll {
+            Poll::Ready(Ok(0)) if !buf.is_empty() => this.finish(BodyOutcome::Completed),
+            Poll::Ready(Ok(n)) => this.advance(*n),
+            Poll::Ready(Err(e)) => this.finish(BodyOutcome::failed(e)),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl AsyncBufRead for ObservedBody {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        this.started();
+        let poll = Pin::new(&mut this.inner).poll_fill_buf(cx);
+        match &poll {
+            Poll::Ready(Ok([])) => finish(&mut this.callback, this.bytes, this.first_read, BodyOutcome::Completed),
+            Poll::Ready(Err(e)) => finish(&mut this.callback, this.bytes, this.first_read, BodyOutcome::failed(e)),
+            _ => {}
+        }
+        poll
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).consume(amt);
+        this.advance(amt);
+    }
+}
+
+impl Drop for ObservedBody {
+    fn drop(&mut self) {
+        self.finish(BodyOutcome::Dropped);
+    }
+}
+
+impl std::fmt::Debug for ObservedBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObservedBody")
+            .field("inner", &self.inner)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
@@ -0,0 +1,191 @@
+use super::body::{has_body, BodyOutcome, BodyStats, ObservedBody};
+use super::metrics::{self, MetricsConfig, OpenTelemetryMetricsMiddleware};
+use super::panic;
+use super::route::ResolvedRoute;
+use super::timer::Timer;
+use super::tracing::{self, OpenTelemetryTracingMiddleware, TracingConfig};
+use opentelemetry::{global::BoxedTracer, trace::TraceContextExt, Context};
+use tide::{Middleware, Next, Request, Result, StatusCode};
+
+/**
+The combined tracing and metrics middleware to be used in tide
+
+Compared to attaching [OpenTelemetryTracingMiddleware] and [OpenTelemetryMetricsMiddleware] separately,
+it handles each request in a single pass: the route is resolved once, the request is timed once
+(the span duration and the recorded histogram value are identical), and bodies and panics are only wrapped once.
+
+Options like [TracingConfig::catch_panics] and [MetricsConfig::catch_panics] apply if enabled in either config.
+Requests to the metrics scraping route are not traced.
+*/
+#[derive(Debug)]
+pub struct OpenTelemetryMiddleware {
+    tracing: OpenTelemetryTracingMiddleware,
+    metrics: OpenTelemetryMetricsMiddleware,
+}
+
+impl Default for OpenTelemetryMiddleware {
+    /// Instantiate the middleware with the global tracer and default configurations
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// app.with(opentelemetry_tide::OpenTelemetryMiddleware::default());
+    /// app.at("/").get(|_| async { Ok("Traced and metricized!") });
+    /// ```
+    fn default() -> Self {
+        Self::from_parts(
+            OpenTelemetryTracingMiddleware::default(),
+            OpenTelemetryMetricsMiddleware::default(),
+        )
+    }
+}
+
+impl OpenTelemetryMiddleware {
+    /// Instantiate the middleware with a provided `BoxedTracer` and `MetricsConfig`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// let tracer = opentelemetry_jaeger::new_pipeline().install_batch(opentelemetry::runtime::AsyncStd).unwrap();
+    /// app.with(opentelemetry_tide::OpenTelemetryMiddleware::new(tracer, Default::default()));
+    /// app.at("/").get(|_| async { Ok("Traced and metricized!") });
+    /// ```
+    pub fn new(tracer: BoxedTracer, metrics_config: MetricsConfig) -> Self {
+        Self::new_with_config(tracer, TracingConfig::default(), metrics_config)
+    }
+
+    /// Instantiate the middleware with a provided `BoxedTracer`, `TracingConfig` and `MetricsConfig`
+    pub fn new_with_config(tracer: BoxedTracer, tracing_config: TracingConfig, metrics_config: MetricsConfig) -> Self {
+        Self::from_parts(
+            OpenTelemetryTracingMiddleware::new_with_config(tracer, tracing_config),
+            OpenTelemetryMetricsMiddleware::new(metrics_config),
+        )
+    }
+
+    pub(crate) fn from_parts(tracing: OpenTelemetryTracingMiddleware, metrics: OpenTelemetryMetricsMiddleware) -> Self {
+        Self { tracing, metrics }
+    }
+
+    /// Sets the route template the middleware is attached to (see `TideExt` for `tide::Route`)
+    pub(crate) fn with_route_template(self, route_template: String) -> Self {
+        Self::from_parts(
+            self.tracing.with_route_template(route_template.clone()),
+            self.metrics.with_route_template(route_template),
+        )
+    }
+
+    fn route<State>(&self, req: &Request<State>) -> ResolvedRoute {
+        ResolvedRoute::new(req, self.metrics.route_template.as_deref())
+    }
+
+    fn catch_panics(&self) -> bool {
+        self.tracing.config.catch_panics || self.metrics.catch_panics
+    }
+
+    fn track_request_body(&self) -> bool {
+        self.tracing.config.track_request_body || self.metrics.track_request_body
+    }
+
+    fn track_response_body(&self) -> bool {
+        self.tracing.config.track_response_body || self.metrics.track_response_body
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for OpenTelemetryMiddleware {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+        if self.metrics.is_metrics_route(&req) {
+            return self.metrics.metrics_response();
+        }
+
+        let method = req.method();
+        let timer = Timer::start();
+        let mut route = self.route(&req);
+        let cx = self.tracing.start_span(&req, &route, Some(timer.start_time()));
+        let labels = self.metrics.labels(&req, &route, &cx);
+        route.pass_on(&mut req);
+
+        if self.track_request_body() && req.len() != Some(0) {
+            let cx = cx.clone();
+            let record = self.metrics.record_request_body(labels.clone());
+            let body = req.take_body();
+            req.set_body(ObservedBody::wrap(body, move |stats, outcome| {
+                record(stats);
+                tracing::record_request_body(&cx, stats, outcome);
+            }));
+        }
+
+        let guard = RequestGuard {
+            cx: cx.clone(),
+            timer,
+            metrics: Some(self.metrics.request_guard(labels, timer)),
+        };
+
+        // call next in the chain
+        let mut res = if self.catch_panics() {
+            tracing::in_server_context(panic::catch_unwind(next.run(req)), &cx)
+                .await
+                .unwrap_or_else(panic::panic_response)
+        } else {
+            tracing::in_server_context(next.run(req), &cx).await
+        };
+
+        let track_response_body = self.track_response_body() && has_body(method, &res);
+        self.tracing.finish_span(&cx, &mut res, track_response_body);
+
+        let status = res.status();
+        if track_response_body {
+            let body = res.take_body();
+            res.set_body(ObservedBody::wrap(body, move |stats, outcome| {
+                guard.finish(status, Some((stats, outcome)))
+            }));
+        } else {
+            guard.finish(status, None);
+        }
+        Ok(res)
+    }
+}
+
+/// Ends the span and records the metrics of a request at the very same time;
+/// if it gets dropped before finishing, the request is recorded as cancelled
+struct RequestGuard {
+    cx: Context,
+    timer: Timer,
+    metrics: Option<metrics::RequestGuard>,
+}
+
+impl RequestGuard {
+    /// Finishes the request, after the response body stream ended if it was tracked
+    fn finish(mut self, status: StatusCode, body: Option<(BodyStats, BodyOutcome)>) {
+        let elapsed = self.timer.elapsed();
+        let end_time = Some(self.timer.end_time(elapsed));
+        if let Some(metrics) = self.metrics.take() {
+            match body {
+                Some((stats, BodyOutcome::Dropped)) => {
+                    tracing::end_with_body(&self.cx, status, stats, BodyOutcome::Dropped, end_time);
+                    metrics.cancel_after(elapsed);
+                }
+                Some((stats, outcome)) => {
+                    tracing::end_with_body(&self.cx, status, stats, outcome, end_time);
+                    metrics.finish_after(status, elapsed);
+                }
+                None => {
+                    tracing::end_span(&self.cx.span(), end_time);
+                    metrics.finish_after(status, elapsed);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if let Some(metrics) = self.metrics.take() {
+            let elapsed = self.timer.elapsed();
+            tracing::end_cancelled(&self.cx, Some(self.timer.end_time(elapsed)));
+            metrics.cancel_after(elapsed);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use super::request_id::RequestId;
+use opentelemetry::{trace::TraceContextExt, Context};
+use serde_json::{Map, Value};
+use tide::{http::mime::Mime, Body, Middleware, Next, Request, Response, Result};
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// The body format of rendered error responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `application/json`, like `{"status": 500, "error": "Internal Server Error", "trace_id": "…", "request_id": "…"}`
+    Json,
+    /// `application/problem+json` as of [RFC 7807](https://datatracker.ietf.org/doc/html/rfc7807),
+    /// with `trace_id`, `request_id` and `trace_url` as extension members
+    ProblemJson,
+}
+
+/**
+Configuration for the error response middleware
+
+Unless you need specific values, [ErrorResponseConfig::default()] should be fine for most use cases.
+*/
+#[derive(Debug, Clone)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct ErrorResponseConfig {
+    /// The body format; problem+json by default
+    pub format: ErrorFormat,
+    /// Include the message of the `tide::Error` for server errors (5xx) as well;
+    /// by default only client errors (4xx) show it, as server errors may leak internals
+    pub expose_server_errors: bool,
+    /// A link template for the trace, like `https://jaeger.example.com/trace/{trace_id}`,
+    /// added as `trace_url` with the `{trace_id}` placeholder replaced
+    pub trace_url_template: Option<String>,
+}
+
+impl Default for ErrorResponseConfig {
+    fn default() -> Self {
+        Self {
+            format: ErrorFormat::ProblemJson,
+            expose_server_errors: false,
+            trace_url_template: None,
+        }
+    }
+}
+
+/**
+Error response middleware, which renders responses carrying a `tide::Error` as JSON including the trace id,
+so that bug reports come with a reference into the traces
+
+Register it after the tracing and the request id middleware, so that the ids are known:
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+let mut app = tide::new();
+app.with_default_middlewares();
+app.with(opentelemetry_tide::RequestIdMiddleware::new());
+app.with(opentelemetry_tide::ErrorResponseMiddleware::new());
+app.at("/").get(|_| async { Err::<String, _>(tide::Error::from_str(418, "I'm a teapot")) });
+```
+*/
+#[derive(Debug, Default)]
+pub struct ErrorResponseMiddleware {
+    config: ErrorResponseConfig,
+}
+
+impl ErrorResponseMiddleware {
+    /// Instantiate the middleware with the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instantiate the middleware with a custom configuration
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// let config = opentelemetry_tide::ErrorResponseConfig {
+    ///     format: opentelemetry_tide::ErrorFormat::Json,
+    ///     trace_url_template: Some("https://jaeger.example.com/trace/{trace_id}".into()),
+    ///     ..Default::default()
+    /// };
+    /// app.with(opentelemetry_tide::ErrorResponseMiddleware::new_with_config(config));
+    /// ```
+    pub fn new_with_config(config: ErrorResponseConfig) -> Self {
+        Self { config }
+    }
+
+    fn render(&self, res: &mut Response, trace_id: Option<String>, request_id: Option<String>) {
+        let status = res.status();
+        let title = status.canonical_reason();
+        let message = res
+            .error()
+            .map(|error| error.to_string())
+            .filter(|_| status.is_client_error() || self.config.expose_server_errors);
+        let trace_url = trace_id.as_ref().and_then(|trace_id| {
+            self.config
+                .trace_url_template
+                .as_ref()
+                .map(|template| template.replace("{trace_id}", trace_id))
+        });
+
+        let mut body = Map::new();
+        let mime = match self.config.format {
+            ErrorFormat::Json => {
+                let _ = body.insert("status".into(), u16::from(status).into());
+                let _ = body.insert("error".into(), title.into());
+                if let Some(message) = message {
+                    let _ = body.insert("message".into(), message.into());
+                }
+                tide::http::mime::JSON
+            }
+            ErrorFormat::ProblemJson => {
+                let _ = body.insert("type".into(), "about:blank".into());
+                let _ = body.insert("title".into(), title.into());
+                let _ = body.insert("status".into(), u16::from(status).into());
+                if let Some(message) = message {
+                    let _ = body.insert("detail".into(), message.into());
+                }
+                PROBLEM_JSON.parse::<Mime>().unwrap_or(tide::http::mime::JSON)
+            }
+        };
+        for (key, value) in [
+            ("trace_id", trace_id),
+            ("request_id", request_id),
+            ("trace_url", trace_url),
+        ] {
+            if let Some(value) = value {
+                let _ = body.insert(key.into(), value.into());
+            }
+        }
+
+        res.set_body(Body::from_string(Value::Object(body).to_string()));
+        res.set_content_type(mime);
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ErrorResponseMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
+        let cx = Context::current();
+        let span_context = cx.span().span_context().clone();
+        let trace_id = Some(span_context)
+            .filter(|span_context| span_context.is_valid())
+            .map(|span_context| span_context.trace_id().to_string());
+        let request_id = req.ext::<RequestId>().map(|request_id| request_id.as_str().to_owned());
+
+        let mut res = next.run(req).await;
+        if res.error().is_some() {
+            self.render(&mut res, trace_id, request_id);
+        }
+        Ok(res)
+    }
+}
@@ -1,13 +1,22 @@
+use super::body::{has_body, BodyOutcome, BodyStats, ObservedBody};
+use super::panic;
+use super::route::ResolvedRoute;
+use super::timer::Timer;
 use http_types::{Body, StatusCode};
 use opentelemetry::{
+    baggage::BaggageExt,
     global,
     metrics::{Counter, Unit, ValueRecorder},
     sdk::resource::Resource,
-    Key, KeyValue,
+    Context, Key, KeyValue,
 };
 use opentelemetry_prometheus::PrometheusExporter;
 use prometheus::{Encoder, TextEncoder};
-use std::time::SystemTime;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tide::{Middleware, Next, Request, Response, Result};
 
 const DEFAULT_METRICS_ROUTE: &str = "/metrics";
@@ -15,6 +24,11 @@ const DEFAULT_METRICS_ROUTE: &str = "/metrics";
 const ROUTE_KEY: Key = Key::from_static_str("http_route");
 const METHOD_KEY: Key = Key::from_static_str("http_method");
 const STATUS_KEY: Key = Key::from_static_str("http_status_code");
+// replaces baggage label values beyond the configured maximum
+const OTHER_LABEL_VALUE: &str = "other";
+const DEFAULT_MAX_BAGGAGE_LABEL_VALUES: usize = 100;
+// non-standard status code for requests the client gave up on, as used by nginx
+const CLIENT_CLOSED_REQUEST: u16 = 499;
 
 // TODO: 31 buckets (+Inf) are a lot;
 // try to find better bucket thresholds with less buckets
@@ -49,12 +63,33 @@ so you don't have to bring this struct into scope with a `use`.
 pub struct MetricsConfig {
     /// Optional vec of key value pairs which then get added as labels to all metrics
     pub global_labels: Option<Vec<KeyValue>>,
+    /// Optional resource describing the service (see [crate::resource]), added as labels to all metrics;
+    /// the `global_labels` take precedence over it
+    pub resource: Option<Resource>,
     /// A vec of histogram boundaries; set your own fine-tuned buckets for your services
     pub boundaries: Vec<f64>,
     /// A vec of summary quantiles (currently no prometheus-exportable metric is using them)
     pub quantiles: Vec<f64>,
     /// The route which will be used for metrics scraping by prometheus
     pub route: String,
+    /// Catch panics of handlers and inner middlewares, count them as internal server errors (500)
+    /// and respond with such an error instead
+    pub catch_panics: bool,
+    /// Measure the request duration until the response body has been fully sent (or failed),
+    /// instead of until the handler returned;
+    /// useful for streaming responses, downloads and server-sent events
+    pub track_response_body: bool,
+    /// Measure the bytes read from the request body and the time spent reading it,
+    /// which helps to tell slow clients (uploads) from slow handlers;
+    /// the body is not buffered for that
+    pub track_request_body: bool,
+    /// Baggage entries (like `tenant.id`) to add as labels to the request metrics, with dots as underscores (`tenant_id`);
+    /// the baggage is taken from the context set by the tracing middleware, so register this middleware after it
+    /// (or use the combined middleware)
+    pub baggage_labels: Vec<String>,
+    /// The maximum number of distinct values per baggage label; further values are recorded as `other`,
+    /// so that clients cannot blow up the number of time series
+    pub max_baggage_label_values: usize,
 }
 
 impl MetricsConfig {
@@ -62,9 +97,15 @@ impl MetricsConfig {
     pub fn new(global_labels: Option<Vec<KeyValue>>, boundaries: Vec<f64>, quantiles: Vec<f64>, route: String) -> Self {
         Self {
             global_labels,
+            resource: None,
             boundaries,
             quantiles,
             route,
+            catch_panics: false,
+            track_response_body: false,
+            track_request_body: false,
+            baggage_labels: Vec::new(),
+            max_baggage_label_values: DEFAULT_MAX_BAGGAGE_LABEL_VALUES,
         }
     }
 }
@@ -84,22 +125,119 @@ impl Default for MetricsConfig {
 #[derive(Debug)]
 pub struct OpenTelemetryMetricsMiddleware {
     route: String,
+    pub(crate) route_template: Option<String>,
+    pub(crate) catch_panics: bool,
+    pub(crate) track_response_body: bool,
+    pub(crate) track_request_body: bool,
     exporter: PrometheusExporter,
+    instruments: Arc<Instruments>,
+    baggage_labels: BaggageLabels,
+}
+
+/// Turns the allowed baggage entries into labels, with a bounded number of values per label
+#[derive(Debug)]
+struct BaggageLabels {
+    // the baggage key and the label key
+    keys: Vec<(Key, Key)>,
+    max_values: usize,
+    seen: Mutex<HashMap<Key, HashSet<String>>>,
+}
+
+impl BaggageLabels {
+    fn new(baggage_keys: Vec<String>, max_values: usize) -> Self {
+        let keys = baggage_keys
+            .into_iter()
+            .map(|baggage_key| {
+                let label_key = baggage_key.replace('.', "_");
+                (Key::new(baggage_key), Key::new(label_key))
+            })
+            .collect();
+        Self {
+            keys,
+            max_values,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn append_to(&self, labels: &mut Vec<KeyValue>, cx: &Context) {
+        if self.keys.is_empty() {
+            return;
+        }
+        let baggage = cx.baggage();
+        let mut seen = match self.seen.lock() {
+            Ok(seen) => seen,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for (baggage_key, label_key) in &self.keys {
+            if let Some(value) = baggage.get(baggage_key.clone()) {
+                let value = value.as_str();
+                let values = seen.entry(label_key.clone()).or_default();
+                let value = if values.contains(value.as_ref()) {
+                    value.into_owned()
+                } else if values.len() < self.max_values {
+                    let _ = values.insert(value.clone().into_owned());
+                    value.into_owned()
+                } else {
+                    OTHER_LABEL_VALUE.to_owned()
+                };
+                labels.push(label_key.clone().string(value));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Instruments {
     request_count: Counter<u64>,
     error_count: Counter<u64>,
+    cancelled_count: Counter<u64>,
     duration: ValueRecorder<f64>,
     duration_ms: ValueRecorder<f64>,
+    request_body_bytes: Counter<u64>,
+    request_body_read_duration: ValueRecorder<f64>,
 }
 
-#[allow(dead_code)]
-fn build_exporter_and_init_meter(config: MetricsConfig) -> PrometheusExporter {
+// the exporter of the most recently installed global meter, for a final export on shutdown
+static INSTALLED_EXPORTER: Mutex<Option<PrometheusExporter>> = Mutex::new(None);
+
+/// Builds a prometheus exporter for the configuration and installs its meter provider globally
+pub(crate) fn build_exporter_and_init_meter(config: &MetricsConfig) -> PrometheusExporter {
     let mut builder = opentelemetry_prometheus::exporter()
-        .with_default_histogram_boundaries(config.boundaries)
-        .with_default_summary_quantiles(config.quantiles);
-    if let Some(global_labels) = config.global_labels {
-        builder = builder.with_resource(Resource::new(global_labels));
+        .with_default_histogram_boundaries(config.boundaries.clone())
+        .with_default_summary_quantiles(config.quantiles.clone());
+    let resource = match (config.resource.clone(), config.global_labels.clone()) {
+        (Some(resource), Some(global_labels)) => Some(resource.merge(&Resource::new(global_labels))),
+        (resource, global_labels) => resource.or_else(|| global_labels.map(Resource::new)),
+    };
+    if let Some(resource) = resource {
+        builder = builder.with_resource(resource);
+    }
+    let exporter = builder.init();
+    if let Ok(mut installed) = INSTALLED_EXPORTER.lock() {
+        *installed = Some(exporter.clone());
     }
-    builder.init()
+    exporter
+}
+
+/// The exporter of the installed global meter, or a newly built and installed one if there is none yet
+fn installed_exporter_or_init(config: &MetricsConfig) -> PrometheusExporter {
+    let installed = INSTALLED_EXPORTER.lock().ok().and_then(|installed| installed.clone());
+    installed.unwrap_or_else(|| build_exporter_and_init_meter(config))
+}
+
+/// Encodes the current state of the installed metrics in the prometheus text format
+#[allow(dead_code)]
+pub(crate) fn export_installed() -> Option<Result<String>> {
+    let exporter = INSTALLED_EXPORTER.lock().ok()?.clone()?;
+    Some(encode(&exporter))
+}
+
+fn encode(exporter: &PrometheusExporter) -> Result<String> {
+    let encoder = TextEncoder::new();
+    let metric_families = exporter.registry().gather();
+    let mut result = Vec::new();
+    encoder.encode(&metric_families, &mut result)?;
+    Ok(String::from_utf8(result)?)
 }
 
 impl OpenTelemetryMetricsMiddleware {
@@ -122,8 +260,20 @@ impl OpenTelemetryMetricsMiddleware {
     /// app.at("/").get(|_| async { Ok("Metricized!") });
     /// ```
     pub fn new(config: MetricsConfig) -> Self {
-        let route = config.route.clone();
-        let exporter = build_exporter_and_init_meter(config);
+        let exporter = build_exporter_and_init_meter(&config);
+        Self::with_exporter(config, exporter)
+    }
+
+    /// Instantiate the middleware on the installed meter provider, installing one only if there is none yet;
+    /// used for the middlewares attached to routes, which would otherwise replace each other's meter provider
+    pub(crate) fn on_installed_meter(config: MetricsConfig) -> Self {
+        let exporter = installed_exporter_or_init(&config);
+        Self::with_exporter(config, exporter)
+    }
+
+    /// Instantiate the middleware on an already installed exporter (see `build_exporter_and_init_meter`)
+    pub(crate) fn with_exporter(config: MetricsConfig, exporter: PrometheusExporter) -> Self {
+        let baggage_labels = BaggageLabels::new(config.baggage_labels, config.max_baggage_label_values);
         // As a starting point we use RED method:
         // * https://www.weave.works/blog/the-red-method-key-metrics-for-microservices-architecture/
         // * https://grafana.com/files/grafanacon_eu_2018/Tom_Wilkie_GrafanaCon_EU_2018.pdf
@@ -140,6 +290,11 @@ impl OpenTelemetryMetricsMiddleware {
             .with_description("failed request count (since start of service)")
             .init();
 
+        let cancelled_count = meter
+            .u64_counter("http_server_requests_cancelled")
+            .with_description("cancelled request count, e.g. due to client disconnects (since start of service)")
+            .init();
+
         let duration = meter
             .f64_value_recorder("http_server_request_duration_seconds")
             .with_unit(Unit::new("seconds"))
@@ -152,17 +307,50 @@ impl OpenTelemetryMetricsMiddleware {
             .with_description("request duration histogram (in milliseconds, since start of service)")
             .init();
 
-        Self {
-            route,
-            exporter,
+        let request_body_bytes = meter
+            .u64_counter("http_server_request_body_bytes")
+            .with_unit(Unit::new("bytes"))
+            .with_description("total bytes read from request bodies (since start of service)")
+            .init();
+
+        let request_body_read_duration = meter
+            .f64_value_recorder("http_server_request_body_read_duration_seconds")
+            .with_unit(Unit::new("seconds"))
+            .with_description("time spent reading request bodies histogram (in seconds, since start of service)")
+            .init();
+
+        let instruments = Arc::new(Instruments {
             request_count,
             error_count,
+            cancelled_count,
             duration,
             duration_ms,
+            request_body_bytes,
+            request_body_read_duration,
+        });
+
+        Self {
+            route: config.route,
+            route_template: None,
+            catch_panics: config.catch_panics,
+            track_response_body: config.track_response_body,
+            track_request_body: config.track_request_body,
+            exporter,
+            instruments,
+            baggage_labels,
         }
     }
 }
 
+impl OpenTelemetryMetricsMiddleware {
+    /// Sets the route template the middleware is attached to (see `TideExt` for `tide::Route`),
+    /// which is then used for the route label
+    pub(crate) fn with_route_template(mut self, route_template: String) -> Self {
+        self.route_template = Some(route_template);
+        self
+    }
+}
+
 impl Default for OpenTelemetryMetricsMiddleware {
     /// Instantiate the middleware with defaults
     ///
@@ -178,43 +366,159 @@ impl Default for OpenTelemetryMetricsMiddleware {
     }
 }
 
+impl OpenTelemetryMetricsMiddleware {
+    /// Whether the request is for the metrics scraping route
+    pub(crate) fn is_metrics_route<State>(&self, req: &Request<State>) -> bool {
+        req.url().path() == self.route
+    }
+
+    /// Renders the metrics for prometheus
+    pub(crate) fn metrics_response(&self) -> Result {
+        let result = encode(&self.exporter)?;
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_content_type(tide::http::mime::PLAIN);
+        res.set_body(Body::from_string(result));
+        Ok(res)
+    }
+
+    /// The labels for the request, without the status code (which is only known after the request)
+    pub(crate) fn labels<State>(&self, req: &Request<State>, route: &ResolvedRoute, cx: &Context) -> Vec<KeyValue> {
+        let mut labels = Vec::with_capacity(3 + self.baggage_labels.keys.len());
+        labels.push(ROUTE_KEY.string(route.route.clone()));
+        labels.push(METHOD_KEY.string(req.method().to_string()));
+        self.baggage_labels.append_to(&mut labels, cx);
+        labels
+    }
+
+    /// Records how the request body was read by the handler
+    pub(crate) fn record_request_body(&self, labels: Vec<KeyValue>) -> impl Fn(BodyStats) + Send + Sync + 'static {
+        let instruments = self.instruments.clone();
+        move |stats| {
+            // a body which was never touched is not worth reporting
+            if let Some(read_time) = stats.read_time {
+                instruments.request_body_bytes.add(stats.bytes, &labels);
+                instruments
+                    .request_body_read_duration
+                    .record(read_time.as_secs_f64(), &labels);
+            }
+        }
+    }
+
+    pub(crate) fn request_guard(&self, labels: Vec<KeyValue>, timer: Timer) -> RequestGuard {
+        RequestGuard {
+            instruments: self.instruments.clone(),
+            labels,
+            timer,
+            finished: false,
+        }
+    }
+}
+
 #[tide::utils::async_trait]
 impl<State: Clone + Send + Sync + 'static> Middleware<State> for OpenTelemetryMetricsMiddleware {
-    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
-        if req.url().path() == self.route {
-            let encoder = TextEncoder::new();
-            let metric_families = self.exporter.registry().gather();
-            let mut result = Vec::new();
-            encoder.encode(&metric_families, &mut result)?;
-            let mut res = Response::new(StatusCode::Ok);
-            res.set_content_type(tide::http::mime::PLAIN);
-            res.set_body(Body::from_bytes(result));
-            Ok(res)
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+        if self.is_metrics_route(&req) {
+            self.metrics_response()
 
         // regular request came in, handle and serve it
         } else {
-            let mut labels = Vec::with_capacity(3);
-            labels.push(ROUTE_KEY.string(req.url().path().to_string()));
-            labels.push(METHOD_KEY.string(req.method().to_string()));
-
-            let timer = SystemTime::now();
+            let mut route = ResolvedRoute::new(&req, self.route_template.as_deref());
+            let labels = self.labels(&req, &route, &Context::current());
+            route.pass_on(&mut req);
 
-            // call next in the chain
-            let res = next.run(req).await;
+            if self.track_request_body && req.len() != Some(0) {
+                let record = self.record_request_body(labels.clone());
+                let body = req.take_body();
+                req.set_body(ObservedBody::wrap(body, move |stats, _| record(stats)));
+            }
 
-            let elapsed = timer.elapsed();
-            let elapsed_sec = elapsed.clone().map(|t| t.as_secs_f64()).unwrap_or_default();
-            let elapsed_ms = elapsed.map(|t| t.as_secs_f64() * 1_000f64).unwrap_or_default();
+            let method = req.method();
+            let guard = self.request_guard(labels, Timer::start());
 
-            labels.push(STATUS_KEY.i64(u16::from(res.status()).into()));
+            // call next in the chain
+            let mut res = if self.catch_panics {
+                panic::catch_unwind(next.run(req))
+                    .await
+                    .unwrap_or_else(panic::panic_response)
+            } else {
+                next.run(req).await
+            };
 
-            if res.status().is_server_error() {
-                self.error_count.add(1, &labels)
+            let status = res.status();
+            if self.track_response_body && has_body(method, &res) {
+                let body = res.take_body();
+                res.set_body(ObservedBody::wrap(body, move |_, outcome| match outcome {
+                    // dropping the guard unfinished records the request as cancelled
+                    BodyOutcome::Dropped => drop(guard),
+                    _ => guard.finish(status),
+                }));
+            } else {
+                guard.finish(status);
             }
-            self.request_count.add(1, &labels);
-            self.duration.record(elapsed_sec, &labels);
-            self.duration_ms.record(elapsed_ms, &labels);
             Ok(res)
         }
     }
 }
+
+/// Records the metrics of a single request when finished;
+/// if it gets dropped before that, the request is recorded as cancelled
+/// (tide drops the request future for example when the client disconnects)
+pub(crate) struct RequestGuard {
+    instruments: Arc<Instruments>,
+    labels: Vec<KeyValue>,
+    timer: Timer,
+    finished: bool,
+}
+
+impl RequestGuard {
+    pub(crate) fn finish(self, status: StatusCode) {
+        let elapsed = self.timer.elapsed();
+        self.finish_after(status, elapsed);
+    }
+
+    /// Records the request as finished after the given duration
+    pub(crate) fn finish_after(mut self, status: StatusCode, elapsed: Duration) {
+        self.finished = true;
+        self.record(status.into(), elapsed);
+    }
+
+    /// Records the request as not completed after the given duration (used by the combined middleware)
+    #[cfg(feature = "trace")]
+    pub(crate) fn cancel_after(mut self, elapsed: Duration) {
+        self.finished = true;
+        self.cancelled(elapsed);
+    }
+
+    fn cancelled(&mut self, elapsed: Duration) {
+        // an uncaught panic unwinds through here, which is not a cancellation
+        if std::thread::panicking() {
+            self.record(StatusCode::InternalServerError.into(), elapsed);
+        } else {
+            self.instruments.cancelled_count.add(1, &self.labels);
+            self.record(CLIENT_CLOSED_REQUEST, elapsed);
+        }
+    }
+
+    fn record(&mut self, status: u16, elapsed: Duration) {
+        let elapsed_sec = elapsed.as_secs_f64();
+        let elapsed_ms = elapsed.as_secs_f64() * 1_000f64;
+
+        self.labels.push(STATUS_KEY.i64(status.into()));
+
+        if (500..=599).contains(&status) {
+            self.instruments.error_count.add(1, &self.labels)
+        }
+        self.instruments.request_count.add(1, &self.labels);
+        self.instruments.duration.record(elapsed_sec, &self.labels);
+        self.instruments.duration_ms.record(elapsed_ms, &self.labels);
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            let elapsed = self.timer.elapsed();
+            self.cancelled(elapsed);
+        }
+    }
+}
@@ -1,5 +1,29 @@
+#[cfg(any(feature = "trace", feature = "metrics"))]
+pub(crate) mod body;
+#[cfg(any(feature = "trace", feature = "metrics"))]
+mod panic;
+#[cfg(any(feature = "trace", feature = "metrics"))]
+mod route;
+#[cfg(feature = "metrics")]
+mod timer;
+
+#[cfg(feature = "trace")]
+pub mod sampling;
+
 #[cfg(feature = "trace")]
 pub mod tracing;
 
+#[cfg(feature = "trace")]
+pub mod access_log;
+
+#[cfg(feature = "trace")]
+pub mod request_id;
+
+#[cfg(feature = "trace")]
+pub mod error_response;
+
 #[cfg(feature = "metrics")]
 pub mod metrics;
+
+#[cfg(all(feature = "trace", feature = "metrics"))]
+pub mod combined;
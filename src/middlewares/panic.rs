@@ -0,0 +1,39 @@
+use futures_util::FutureExt;
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+use tide::{Response, StatusCode};
+
+/// Message of a panic caught by one of the middlewares
+///
+/// It is stored in the extensions of the generated response,
+/// so outer middlewares of this crate can still report the panic.
+/// Only the tracing middleware reports the message itself.
+#[derive(Clone, Debug)]
+pub(crate) struct PanicMessage(#[cfg_attr(not(feature = "trace"), allow(dead_code))] pub(crate) String);
+
+impl PanicMessage {
+    fn new(payload: Box<dyn Any + Send>) -> Self {
+        Self(panic_message(payload))
+    }
+}
+
+/// Polls the future and catches a panic, if it unwinds
+pub(crate) async fn catch_unwind<F: Future>(future: F) -> std::result::Result<F::Output, PanicMessage> {
+    AssertUnwindSafe(future).catch_unwind().await.map_err(PanicMessage::new)
+}
+
+/// Turns a caught panic into an internal server error response
+pub(crate) fn panic_response(message: PanicMessage) -> Response {
+    let mut res = Response::new(StatusCode::InternalServerError);
+    res.insert_ext(message);
+    res
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_owned()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
@@ -0,0 +1,193 @@
+use opentelemetry::{
+    sdk::trace::IdGenerator as RandomIdGenerator,
+    trace::{IdGenerator, TraceContextExt},
+    Context, Key,
+};
+use std::{fmt, sync::Arc};
+use tide::{
+    http::headers::{HeaderName, HeaderValue},
+    Middleware, Next, Request, Result,
+};
+
+const DEFAULT_HEADER: &str = "x-request-id";
+const HTTP_REQUEST_ID: Key = Key::from_static_str("http.request_id");
+// incoming ids end up in logs and traces, so they must not be arbitrary client input
+const MAX_INCOMING_LEN: usize = 128;
+
+/// The id of the current request, stored in the request extensions by [RequestIdMiddleware]
+///
+/// ```rust,no_run
+/// # async fn handler(req: tide::Request<()>) -> tide::Result {
+/// let request_id = req.ext::<opentelemetry_tide::RequestId>().map(|id| id.as_str());
+/// # Ok("".into())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The request id as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// How new request ids get generated
+#[derive(Clone, Default)]
+pub enum RequestIdGenerator {
+    /// 128 random bits as 32 lowercase hex characters (like a trace id)
+    #[default]
+    Random,
+    /// A random (version 4) UUID, like `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    #[cfg(feature = "request-id-uuid")]
+    Uuid,
+    /// A ULID, lexicographically sortable by time, like `01ARZ3NDEKTSV4RRFFQ69G5FAV`
+    #[cfg(feature = "request-id-ulid")]
+    Ulid,
+    /// A custom generator function
+    Custom(Arc<dyn Fn() -> String + Send + Sync>),
+}
+
+impl fmt::Debug for RequestIdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Random => f.write_str("Random"),
+            #[cfg(feature = "request-id-uuid")]
+            Self::Uuid => f.write_str("Uuid"),
+            #[cfg(feature = "request-id-ulid")]
+            Self::Ulid => f.write_str("Ulid"),
+            Self::Custom(_) => f.write_str("Custom(Fn() -> String)"),
+        }
+    }
+}
+
+impl RequestIdGenerator {
+    fn generate(&self) -> String {
+        match self {
+            Self::Random => RandomIdGenerator::default().new_trace_id().to_string(),
+            #[cfg(feature = "request-id-uuid")]
+            Self::Uuid => uuid::Uuid::new_v4().to_string(),
+            #[cfg(feature = "request-id-ulid")]
+            Self::Ulid => ulid::Ulid::new().to_string(),
+            Self::Custom(generate) => generate(),
+        }
+    }
+}
+
+/**
+Configuration for the request id middleware
+
+Unless you need specific values, [RequestIdConfig::default()] should be fine for most use cases.
+*/
+#[derive(Debug, Clone)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct RequestIdConfig {
+    /// The header to read the request id from and to echo it on the response; `X-Request-Id` by default
+    pub header: HeaderName,
+    /// How new request ids get generated
+    pub generator: RequestIdGenerator,
+    /// Reuse the request id sent by the client or an upstream proxy, if present and well-formed
+    /// (up to 128 visible ASCII characters); otherwise always generate a new one
+    pub trust_incoming: bool,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header: HeaderName::from(DEFAULT_HEADER),
+            generator: RequestIdGenerator::default(),
+            trust_incoming: true,
+        }
+    }
+}
+
+/**
+Request id middleware, which reuses or generates a request id per request
+
+The id is stored as [RequestId] in the request extensions, echoed in the response header,
+recorded as `http.request_id` attribute on the current (server) span and logged by the [crate::AccessLogMiddleware].
+
+Register it after the tracing middleware and before the access log middleware:
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+let mut app = tide::new();
+app.with_default_middlewares();
+app.with(opentelemetry_tide::RequestIdMiddleware::new());
+app.with(opentelemetry_tide::AccessLogMiddleware::new());
+app.at("/").get(|_| async { Ok("Identified!") });
+```
+*/
+#[derive(Debug, Default)]
+pub struct RequestIdMiddleware {
+    config: RequestIdConfig,
+}
+
+impl RequestIdMiddleware {
+    /// Instantiate the middleware with the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instantiate the middleware with a custom configuration
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// let config = opentelemetry_tide::RequestIdConfig {
+    ///     header: "x-correlation-id".parse().unwrap(),
+    ///     trust_incoming: false,
+    ///     ..Default::default()
+    /// };
+    /// app.with(opentelemetry_tide::RequestIdMiddleware::new_with_config(config));
+    /// ```
+    pub fn new_with_config(config: RequestIdConfig) -> Self {
+        Self { config }
+    }
+
+    fn request_id<State>(&self, req: &Request<State>) -> RequestId {
+        let incoming = req
+            .header(&self.config.header)
+            .map(|values| values.last().as_str())
+            .filter(|_| self.config.trust_incoming)
+            .filter(|id| is_well_formed(id));
+        match incoming {
+            Some(id) => RequestId(id.to_owned()),
+            None => RequestId(self.config.generator.generate()),
+        }
+    }
+}
+
+fn is_well_formed(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_INCOMING_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestIdMiddleware {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+        let request_id = self.request_id(&req);
+
+        let cx = Context::current();
+        let span = cx.span();
+        if span.is_recording() {
+            span.set_attribute(HTTP_REQUEST_ID.string(request_id.0.clone()));
+        }
+
+        let header_value = HeaderValue::from_bytes(request_id.0.clone().into_bytes());
+        let _ = req.set_ext(request_id);
+
+        let mut res = next.run(req).await;
+        if let Ok(header_value) = header_value {
+            res.insert_header(&self.config.header, header_value);
+        }
+        Ok(res)
+    }
+}
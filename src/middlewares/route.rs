@@ -0,0 +1,114 @@
+use tide::{http::Url, Request};
+
+/// Left in the request extensions by the middlewares of this crate, in case the request is passed on to a nested app;
+/// tide strips the nest prefix off the path, so the middlewares of the nested app would not know it otherwise
+#[derive(Clone, Debug)]
+struct NestPrefix {
+    // the prefix of the app the middleware leaving this is attached to, including the prefixes of outer nestings
+    prefix: String,
+    // the template of the route the middleware is attached to, which is the nest prefix if the request is nested
+    template: Option<String>,
+    // the request url before any prefix was stripped off
+    url: Url,
+    // the path as seen by the app the middleware is attached to, to tell the middlewares of the same app
+    // (which still see it) from the ones of a nested app
+    path: String,
+}
+
+impl NestPrefix {
+    /// The prefix of the nested app, which the request was passed on to with the given (stripped) path
+    fn nested_prefix(&self, path: &str) -> String {
+        match &self.template {
+            Some(template) => template.trim_end_matches('/').to_owned(),
+            // tide strips the prefix off, leaving the rest (or the root, if there was no rest)
+            None => {
+                let own = self
+                    .path
+                    .strip_suffix(path)
+                    .or_else(|| Some(self.path.as_str()).filter(|_| path == "/"))
+                    .unwrap_or_default();
+                format!("{}{}", self.prefix, own.trim_end_matches('/'))
+            }
+        }
+    }
+}
+
+/// The route of a request as reported by the middlewares
+#[derive(Debug)]
+pub(crate) struct ResolvedRoute {
+    /// The route template if the middleware is attached to a route (with a wildcard for nested apps),
+    /// the path otherwise; with the nest prefix
+    pub(crate) route: String,
+    /// Whether the route is based on the route template
+    #[cfg(feature = "trace")]
+    pub(crate) is_template: bool,
+    /// The request url before the nest prefix was stripped off, if it was
+    #[cfg(feature = "trace")]
+    original_url: Option<Url>,
+    pass_on: Option<NestPrefix>,
+}
+
+impl ResolvedRoute {
+    /**
+    Resolves the route of a request for a middleware, attached to a route with the given template or not
+
+    If the path has more segments than the template, the request is about to be passed on to a nested app,
+    and the route is the template with a `*` wildcard segment appended, keeping the route low in cardinality;
+    middlewares of the nested app prepend the prefix to their own route, see [ResolvedRoute::pass_on].
+
+    Nesting is not detected by the route parameter tide passes the rest of the path in,
+    as looking up a missing parameter builds an error (with a backtrace, if enabled) for every other request.
+    */
+    pub(crate) fn new<State>(req: &Request<State>, template: Option<&str>) -> Self {
+        let path = req.url().path();
+        let (prefix, original_url) = match req.ext::<NestPrefix>() {
+            // left by a middleware of the same app, like an app middleware in front of a route middleware
+            Some(nest) if nest.path == path => {
+                (nest.prefix.clone(), (!nest.prefix.is_empty()).then(|| nest.url.clone()))
+            }
+            Some(nest) => (nest.nested_prefix(path), Some(nest.url.clone())),
+            None => (String::new(), None),
+        };
+        let nesting = template.is_some_and(|template| is_nesting(template, path));
+
+        let route = match template {
+            Some(template) if nesting => format!("{}{}/*", prefix, template.trim_end_matches('/')),
+            Some(template) => format!("{}{}", prefix, template),
+            None => format!("{}{}", prefix, path),
+        };
+        // an app middleware cannot tell whether the request is nested, so it always leaves the prefix
+        let pass_on = (nesting || template.is_none()).then(|| NestPrefix {
+            template: template.map(|template| format!("{}{}", prefix, template)),
+            url: original_url.clone().unwrap_or_else(|| req.url().clone()),
+            path: path.to_owned(),
+            prefix,
+        });
+        Self {
+            route,
+            #[cfg(feature = "trace")]
+            is_template: template.is_some(),
+            #[cfg(feature = "trace")]
+            original_url,
+            pass_on,
+        }
+    }
+
+    /// Lets the middlewares of a nested app, which the request is passed on to, know about the nest prefix
+    pub(crate) fn pass_on<State>(&mut self, req: &mut Request<State>) {
+        if let Some(nest) = self.pass_on.take() {
+            let _ = req.set_ext(nest);
+        }
+    }
+
+    /// The request url, before the nest prefix was stripped off
+    #[cfg(feature = "trace")]
+    pub(crate) fn url<'a, State>(&'a self, req: &'a Request<State>) -> &'a Url {
+        self.original_url.as_ref().unwrap_or_else(|| req.url())
+    }
+}
+
+/// Whether the path goes beyond the route template, which a route middleware only sees for nested apps
+fn is_nesting(template: &str, path: &str) -> bool {
+    let segments = |path: &str| path.split('/').filter(|segment| !segment.is_empty()).count();
+    !template.contains('*') && segments(path) > segments(template)
+}
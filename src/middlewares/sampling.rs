@@ -0,0 +1,342 @@
+use opentelemetry::{
+    sdk::{
+        trace::{IdGenerator as RandomIdGenerator, Sampler, SamplingDecision, SamplingResult, ShouldSample},
+        InstrumentationLibrary,
+    },
+    trace::{IdGenerator, SpanKind, TraceContextExt, TraceId, TraceState},
+    Context,
+};
+use std::{collections::HashMap, fmt, sync::Mutex, time::Instant};
+use tide::{http::headers::HeaderName, Request};
+
+const DEFAULT_DEBUG_HEADER: &str = "x-debug-trace";
+const DEFAULT_TRACE_ID_HEADER: &str = "x-trace-id";
+// the route templates getting a rate limiter of their own per rule, so the limiters cannot grow unbounded
+const MAX_RATE_LIMITED_ROUTES: usize = 100;
+// the key of the rate limiter shared by the requests without a known route template and the routes beyond the maximum
+const OTHER_ROUTES: &str = "other";
+
+/// How the requests of a route get sampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteSampling {
+    /// Sample every request
+    Always,
+    /// Sample no request
+    Never,
+    /// Sample the given ratio (between `0.0` and `1.0`) of the requests, decided by the trace id;
+    /// services using the same ratio agree on the decision for a trace
+    Ratio(f64),
+    /// Sample up to the given number of requests per second for each route template matching the rule;
+    /// requests whose route template the middleware does not know (see [crate::TideExt]) share a single budget
+    /// per rule, as their paths would multiply it, and so do the route templates past 100 per rule
+    RateLimit(f64),
+}
+
+/// A sampling rule for the tracing middleware, see [crate::TracingConfig::sampling_rules]
+///
+/// The route is either matched exactly, or, with a trailing `*`, as a prefix;
+/// it is compared with the route template if the middleware knows it (see [crate::TideExt]), otherwise with the request path.
+///
+/// ```rust,no_run
+/// use opentelemetry_tide::{RouteSampling, SamplingRule};
+///
+/// let rules = vec![
+///     SamplingRule::new("/checkout", RouteSampling::Always),
+///     SamplingRule::new("/search", RouteSampling::Ratio(0.01)),
+///     SamplingRule::new("/static/*", RouteSampling::Never),
+///     SamplingRule::new("/api/*", RouteSampling::RateLimit(10.0)),
+/// ];
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingRule {
+    /// The route (or route prefix, with a trailing `*`) this rule applies to
+    pub route: String,
+    /// How the matching requests get sampled
+    pub sampling: RouteSampling,
+}
+
+impl SamplingRule {
+    /// Creates a rule for the given route (or route prefix, with a trailing `*`)
+    pub fn new(route: impl Into<String>, sampling: RouteSampling) -> Self {
+        Self {
+            route: route.into(),
+            sampling,
+        }
+    }
+
+    fn matches(&self, route: &str) -> bool {
+        route_matches(&self.route, route)
+    }
+}
+
+/// Matches a route exactly, or, with a trailing `*` in the pattern, by prefix
+pub(crate) fn route_matches(pattern: &str, route: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => route.starts_with(prefix),
+        None => route == pattern,
+    }
+}
+
+/// The sampling decision for a request, to be applied to the span builder
+#[derive(Debug)]
+pub(crate) struct RouteSamplingDecision {
+    /// The trace id the decision was made for, if the span does not continue a remote trace
+    pub(crate) trace_id: Option<TraceId>,
+    pub(crate) result: SamplingResult,
+}
+
+/// Applies the sampling rules of the tracing middleware; the first matching rule wins
+#[derive(Debug, Default)]
+pub(crate) struct RouteSampler {
+    rules: Vec<(SamplingRule, Option<RouteRateLimiters>)>,
+}
+
+impl RouteSampler {
+    pub(crate) fn new(rules: &[SamplingRule]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let limiter = match rule.sampling {
+                    RouteSampling::RateLimit(per_second) => Some(RouteRateLimiters::new(per_second)),
+                    _ => None,
+                };
+                (rule.clone(), limiter)
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Decides about sampling for the route (a template or else a path), or `None` if no rule matches
+    /// and the tracer's sampler shall decide
+    pub(crate) fn should_sample(
+        &self,
+        route: &str,
+        is_template: bool,
+        parent_cx: &Context,
+    ) -> Option<RouteSamplingDecision> {
+        let (rule, limiter) = self.rules.iter().find(|(rule, _)| rule.matches(route))?;
+        let (parent_trace_id, trace_state) = parent_trace(parent_cx);
+
+        let mut trace_id = None;
+        let sampled = match rule.sampling {
+            RouteSampling::Always => true,
+            RouteSampling::Never => false,
+            RouteSampling::Ratio(ratio) => {
+                let id = parent_trace_id.unwrap_or_else(|| RandomIdGenerator::default().new_trace_id());
+                trace_id = Some(id).filter(|_| parent_trace_id.is_none());
+                let library = InstrumentationLibrary::default();
+                let sampler = Sampler::TraceIdRatioBased(ratio);
+                let result = sampler.should_sample(None, id, "", &SpanKind::Server, &[], &[], &library);
+                result.decision == SamplingDecision::RecordAndSample
+            }
+            RouteSampling::RateLimit(_) => {
+                let template = Some(route).filter(|_| is_template);
+                limiter.as_ref().is_some_and(|limiter| limiter.try_acquire(template))
+            }
+        };
+
+        Some(RouteSamplingDecision::new(sampled, trace_id, trace_state))
+    }
+
+    /// Samples the request regardless of the rules, the tracer's sampler and the upstream flag
+    pub(crate) fn force(parent_cx: &Context) -> RouteSamplingDecision {
+        let (_, trace_state) = parent_trace(parent_cx);
+        RouteSamplingDecision::new(true, None, trace_state)
+    }
+}
+
+impl RouteSamplingDecision {
+    fn new(sampled: bool, trace_id: Option<TraceId>, trace_state: TraceState) -> Self {
+        Self {
+            trace_id,
+            result: SamplingResult {
+                decision: if sampled {
+                    SamplingDecision::RecordAndSample
+                } else {
+                    SamplingDecision::Drop
+                },
+                attributes: Vec::new(),
+                trace_state,
+            },
+        }
+    }
+}
+
+/// The trace id and trace state of a valid parent span
+fn parent_trace(parent_cx: &Context) -> (Option<TraceId>, TraceState) {
+    let span = parent_cx.span();
+    let parent_span_context = span.span_context();
+    if parent_span_context.is_valid() {
+        (
+            Some(parent_span_context.trace_id()),
+            parent_span_context.trace_state().clone(),
+        )
+    } else {
+        (None, TraceState::default())
+    }
+}
+
+/**
+Configuration of the debug header, which forces a request to be traced regardless of the sampling
+
+A request carrying the header with the configured secret gets its server span sampled and recorded,
+even if the sampler (or an upstream service) decided against it,
+and the trace id is returned in a response header, so it can be looked up right away.
+Requests without the header or with a wrong secret are sampled as usual.
+
+```rust,no_run
+let config = opentelemetry_tide::TracingConfig {
+    debug_trace: Some(opentelemetry_tide::DebugTraceConfig::new(std::env::var("DEBUG_TRACE_SECRET").unwrap())),
+    ..Default::default()
+};
+```
+
+A request like `curl -i -H 'X-Debug-Trace: <secret>' https://…` then shows the trace id as `X-Trace-Id` header.
+*/
+#[derive(Clone)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct DebugTraceConfig {
+    /// The request header carrying the secret; `X-Debug-Trace` by default
+    pub header: HeaderName,
+    /// The secret which authorises forced tracing; an empty secret authorises nobody
+    pub secret: String,
+    /// The response header returning the trace id of a forced trace; `X-Trace-Id` by default
+    pub trace_id_header: HeaderName,
+}
+
+impl fmt::Debug for DebugTraceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DebugTraceConfig")
+            .field("header", &self.header)
+            .field("secret", &"<redacted>")
+            .field("trace_id_header", &self.trace_id_header)
+            .finish()
+    }
+}
+
+impl DebugTraceConfig {
+    /// Creates the configuration for the given secret with the default headers
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            header: HeaderName::from(DEFAULT_DEBUG_HEADER),
+            secret: secret.into(),
+            trace_id_header: HeaderName::from(DEFAULT_TRACE_ID_HEADER),
+        }
+    }
+
+    /// Whether the request carries the debug header with the configured secret
+    pub(crate) fn is_authorised<State>(&self, req: &Request<State>) -> bool {
+        !self.secret.is_empty()
+            && req
+                .header(&self.header)
+                .is_some_and(|values| values.iter().any(|value| secret_eq(value.as_str(), &self.secret)))
+    }
+}
+
+/// Compares in constant time (for a given length), so response times don't reveal the secret
+fn secret_eq(given: &str, secret: &str) -> bool {
+    given.len() == secret.len() && given.bytes().zip(secret.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Marks the context of a server span which has been forced by the debug header
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DebugTraced;
+
+/// The rate limiters of a rule, one per route template, with a bounded number of templates
+#[derive(Debug)]
+struct RouteRateLimiters {
+    per_second: f64,
+    limiters: Mutex<HashMap<String, RateLimiter>>,
+}
+
+impl RouteRateLimiters {
+    fn new(per_second: f64) -> Self {
+        Self {
+            per_second,
+            limiters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn try_acquire(&self, template: Option<&str>) -> bool {
+        let mut limiters = match self.limiters.lock() {
+            Ok(limiters) => limiters,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // route templates start with a slash, so they cannot collide with the shared key
+        let route = match template {
+            Some(template) if limiters.contains_key(template) || limiters.len() < MAX_RATE_LIMITED_ROUTES => template,
+            _ => OTHER_ROUTES,
+        };
+        limiters
+            .entry(route.to_owned())
+            .or_insert_with(|| RateLimiter::new(self.per_second))
+            .try_acquire()
+    }
+}
+
+/// A token bucket, refilled continuously and holding up to one second worth of tokens (but at least one)
+#[derive(Debug)]
+struct RateLimiter {
+    per_second: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_second: f64) -> Self {
+        let per_second = per_second.max(0.0);
+        let capacity = if per_second > 0.0 { per_second.max(1.0) } else { 0.0 };
+        Self {
+            per_second,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.per_second;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_templates_get_a_budget_of_their_own() {
+        let limiters = RouteRateLimiters::new(1.0);
+        assert!(limiters.try_acquire(Some("/api/users/:id")));
+        assert!(limiters.try_acquire(Some("/api/orders/:id")));
+        assert!(!limiters.try_acquire(Some("/api/users/:id")));
+        assert!(!limiters.try_acquire(Some("/api/orders/:id")));
+    }
+
+    #[test]
+    fn requests_without_a_template_share_a_budget() {
+        let limiters = RouteRateLimiters::new(1.0);
+        assert!(limiters.try_acquire(None));
+        assert!(!limiters.try_acquire(None));
+    }
+
+    #[test]
+    fn route_templates_beyond_the_maximum_share_a_budget() {
+        let limiters = RouteRateLimiters::new(1.0);
+        for index in 0..MAX_RATE_LIMITED_ROUTES {
+            assert!(limiters.try_acquire(Some(&format!("/route/{}", index))));
+        }
+        assert!(limiters.try_acquire(Some("/one/more")));
+        assert!(!limiters.try_acquire(Some("/and/another")));
+        assert!(!limiters.try_acquire(None));
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(feature = "trace")]
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
+
+/// A monotonic request timer, which can also derive wall clock timestamps (as used for spans) from it
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timer {
+    instant: Instant,
+    // only the combined middleware needs timestamps
+    #[cfg(feature = "trace")]
+    start_time: SystemTime,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Self {
+            instant: Instant::now(),
+            #[cfg(feature = "trace")]
+            start_time: SystemTime::now(),
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.instant.elapsed()
+    }
+
+    /// The wall clock time after the given duration since the start
+    #[cfg(feature = "trace")]
+    pub(crate) fn end_time(&self, elapsed: Duration) -> SystemTime {
+        self.start_time + elapsed
+    }
+}
@@ -1,20 +1,68 @@
-use http_types::headers::{HeaderName, HeaderValue};
-use kv_log_macro as log;
+use super::body::{has_body, BodyOutcome, BodyStats, ObservedBody};
+use super::panic::{self, PanicMessage};
+use super::route::ResolvedRoute;
+use super::sampling::{DebugTraceConfig, DebugTraced, RouteSampler, SamplingRule};
+use crate::propagation::{HeaderExtractor, HeaderInjector};
+use http_types::headers::HeaderValue;
 use opentelemetry::{
+    baggage::BaggageExt,
     global::{self, BoxedTracer},
-    trace::{FutureExt, Span, SpanKind, StatusCode, TraceContextExt, Tracer, TracerProvider},
-    Context,
+    propagation::TextMapPropagator,
+    trace::{FutureExt, Span, SpanKind, SpanRef, StatusCode, TraceContextExt, Tracer, TracerProvider},
+    Context, Key, KeyValue,
 };
 use opentelemetry_semantic_conventions::{resource, trace};
-use std::collections::HashMap;
-use std::{convert::TryFrom, net::IpAddr, net::SocketAddr, str::FromStr};
-use tide::{http::Version, Middleware, Next, Request, Result};
+use std::{convert::TryFrom, future::Future, net::IpAddr, net::SocketAddr, str::FromStr, sync::Arc, time::SystemTime};
+use tide::{http::Version, Middleware, Next, Request, Response, Result};
 use url::Url;
 
+const HTTP_CANCELLED: Key = Key::from_static_str("http.cancelled");
+const HTTP_REQUEST_BODY_READ_TIME_MS: Key = Key::from_static_str("http.request_body_read_time_ms");
+// replaced by the actual span name once the span turns out to be recording
+const PENDING_SPAN_NAME: &str = "HTTP request";
+
+/**
+Configuration for the tracing middleware
+
+Unless you need specific values, [TracingConfig::default()] should be fine for most use cases.
+*/
+#[derive(Debug, Default)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct TracingConfig {
+    /// Catch panics of handlers and inner middlewares, record them on the server span
+    /// and respond with an internal server error (500) instead
+    pub catch_panics: bool,
+    /// Keep the server span open until the response body has been fully sent (or failed),
+    /// instead of ending it as soon as the handler returned;
+    /// useful for streaming responses, downloads and server-sent events
+    pub track_response_body: bool,
+    /// Record the bytes read from the request body and the time spent reading it,
+    /// which helps to tell slow clients (uploads) from slow handlers;
+    /// the body is not buffered for that
+    pub track_request_body: bool,
+    /// Route-aware sampling, overriding the sampler of the tracer provider for the matching routes;
+    /// the first matching rule wins, requests of other routes are left to the tracer's sampler
+    pub sampling_rules: Vec<SamplingRule>,
+    /// A debug header which forces the request to be traced and returns the trace id, see [DebugTraceConfig]
+    pub debug_trace: Option<DebugTraceConfig>,
+    /// The propagator extracting the parent context from the request headers;
+    /// the global text map propagator if not set
+    pub extract_propagator: Option<Arc<dyn TextMapPropagator + Send + Sync>>,
+    /// The propagator injecting the trace context into the response headers;
+    /// the global text map propagator if not set
+    pub inject_propagator: Option<Arc<dyn TextMapPropagator + Send + Sync>>,
+    /// Baggage entries (like `tenant.id`) to record as attributes of the server span, under the same key;
+    /// entries which are not listed here are ignored
+    pub baggage_attributes: Vec<String>,
+}
+
 /// The middleware struct to be used in tide
 #[derive(Debug)]
 pub struct OpenTelemetryTracingMiddleware {
     tracer: BoxedTracer,
+    pub(crate) config: TracingConfig,
+    route_template: Option<String>,
+    sampler: RouteSampler,
 }
 
 impl Default for OpenTelemetryTracingMiddleware {
@@ -37,7 +85,38 @@ impl OpenTelemetryTracingMiddleware {
     /// app.at("/").get(|_| async { Ok("Traced!") });
     /// ```
     pub fn new(tracer: BoxedTracer) -> Self {
-        Self { tracer }
+        Self::new_with_config(tracer, TracingConfig::default())
+    }
+
+    /// Instantiate the middleware with a provided `BoxedTracer` and `TracingConfig`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let mut app = tide::new();
+    /// let tracer = opentelemetry_jaeger::new_pipeline().install_batch(opentelemetry::runtime::AsyncStd).unwrap();
+    /// let config = opentelemetry_tide::TracingConfig {
+    ///     catch_panics: true,
+    ///     ..Default::default()
+    /// };
+    /// app.with(opentelemetry_tide::OpenTelemetryTracingMiddleware::new_with_config(tracer, config));
+    /// app.at("/").get(|_| async { Ok("Traced!") });
+    /// ```
+    pub fn new_with_config(tracer: BoxedTracer, config: TracingConfig) -> Self {
+        let sampler = RouteSampler::new(&config.sampling_rules);
+        Self {
+            tracer,
+            config,
+            route_template: None,
+            sampler,
+        }
+    }
+
+    /// Sets the route template the middleware is attached to (see `TideExt` for `tide::Route`),
+    /// which is then used for the span name and the `http.route` attribute
+    pub(crate) fn with_route_template(mut self, route_template: String) -> Self {
+        self.route_template = Some(route_template);
+        self
     }
 
     /// Instantiate the middleware with the global tracer
@@ -56,28 +135,119 @@ impl OpenTelemetryTracingMiddleware {
     }
 }
 
-#[tide::utils::async_trait]
-impl<State: Clone + Send + Sync + 'static> Middleware<State> for OpenTelemetryTracingMiddleware {
-    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
+impl OpenTelemetryTracingMiddleware {
+    /// Extracts the remote parent context and starts the server span for the request
+    pub(crate) fn start_span<State>(
+        &self,
+        req: &Request<State>,
+        route: &ResolvedRoute,
+        start_time: Option<SystemTime>,
+    ) -> Context {
         // gather trace data from request, used later to conditionally add remote trace info from upstream service
-        let mut req_headers = HashMap::new();
-        for (k, v) in req.iter() {
-            let _ = req_headers.insert(k.to_string(), v.last().to_string());
+        let extractor = HeaderExtractor::new(req);
+        let parent_cx = match &self.config.extract_propagator {
+            Some(propagator) => propagator.extract(&extractor),
+            None => global::get_text_map_propagator(|propagator| propagator.extract(&extractor)),
+        };
+
+        // decided upfront, as the rules only need the route
+        let debug_traced = self
+            .config
+            .debug_trace
+            .as_ref()
+            .is_some_and(|debug_trace| debug_trace.is_authorised(req));
+        let sampling = if debug_traced {
+            Some(RouteSampler::force(&parent_cx))
+        } else {
+            self.sampler.should_sample(&route.route, route.is_template, &parent_cx)
+        };
+
+        let mut span_builder = self.tracer.span_builder(PENDING_SPAN_NAME).with_kind(SpanKind::Server);
+        if let Some(start_time) = start_time {
+            span_builder = span_builder.with_start_time(start_time);
+        }
+        // the sampler of the tracer provider, if it is asked at all, gets the values which are known anyway;
+        // those which take formatting the url or looking up headers are only built if the span is recorded
+        let sampler_asked = match sampling {
+            Some(sampling) => {
+                if let Some(trace_id) = sampling.trace_id {
+                    span_builder = span_builder.with_trace_id(trace_id);
+                }
+                span_builder = span_builder.with_sampling_result(sampling.result);
+                false
+            }
+            None => {
+                if let Some(name) = Self::template_span_name(req, route) {
+                    span_builder.name = name.into();
+                }
+                span_builder = span_builder.with_attributes(Self::known_attributes(req, route));
+                true
+            }
+        };
+        let mut span = if parent_cx.span().span_context().is_remote() {
+            span_builder.start_with_context(&self.tracer, &parent_cx)
+        } else {
+            span_builder.start(&self.tracer)
+        };
+        if span.is_recording() {
+            if !sampler_asked {
+                if let Some(name) = Self::template_span_name(req, route) {
+                    span.update_name(name);
+                }
+                for attribute in Self::known_attributes(req, route) {
+                    span.set_attribute(attribute);
+                }
+            }
+            if !route.is_template {
+                span.update_name(format!("{} {}", req.method(), route.url(req)));
+            }
+            for attribute in Self::attributes(req, route) {
+                span.set_attribute(attribute);
+            }
+            let baggage = parent_cx.baggage();
+            for key in &self.config.baggage_attributes {
+                if let Some(value) = baggage.get(key.clone()) {
+                    span.set_attribute(KeyValue::new(key.clone(), value.clone()));
+                }
+            }
+            span.add_event("request.started".to_owned(), vec![]);
         }
-        let parent_cx = global::get_text_map_propagator(|propagator| propagator.extract(&req_headers));
-        drop(req_headers);
 
-        let method = req.method();
-        let url = req.url().clone();
+        // derived from the parent context, so the incoming baggage is available within the request
+        let cx = parent_cx.with_span(span);
+        if debug_traced {
+            cx.with_value(DebugTraced)
+        } else {
+            cx
+        }
+    }
 
-        let mut attributes = Vec::with_capacity(13); // 7 required and 6 optional values
+    /// The span name if the route template is known; otherwise it is built from the url
+    fn template_span_name<State>(req: &Request<State>, route: &ResolvedRoute) -> Option<String> {
+        route.is_template.then(|| format!("{} {}", req.method(), route.route))
+    }
+
+    /// The attributes which are cheap to get, so they are passed to the sampler of the tracer provider
+    fn known_attributes<State>(req: &Request<State>, route: &ResolvedRoute) -> Vec<KeyValue> {
+        let mut attributes = Vec::with_capacity(2);
+        attributes.push(trace::HTTP_METHOD.string(req.method().to_string()));
+        if route.is_template {
+            attributes.push(trace::HTTP_ROUTE.string(route.route.clone()));
+        }
+        attributes
+    }
+
+    /// The attributes which are only built for recording spans
+    fn attributes<State>(req: &Request<State>, route: &ResolvedRoute) -> Vec<KeyValue> {
+        let url = route.url(req);
+
+        let mut attributes = Vec::with_capacity(12); // 5 required and 7 optional values
         attributes.push(resource::TELEMETRY_SDK_NAME.string(crate::CRATE_NAME));
         attributes.push(resource::TELEMETRY_SDK_VERSION.string(crate::VERSION));
         attributes.push(resource::TELEMETRY_SDK_LANGUAGE.string("rust"));
-        attributes.push(trace::HTTP_METHOD.string(method.to_string()));
         attributes.push(trace::HTTP_SCHEME.string(url.scheme().to_owned()));
         attributes.push(trace::HTTP_URL.string(url.to_string()));
-        attributes.push(trace::HTTP_TARGET.string(http_target(&url)));
+        attributes.push(trace::HTTP_TARGET.string(http_target(url)));
 
         if let Some(version) = req.version() {
             attributes.push(trace::HTTP_FLAVOR.string(http_version_str(version)));
@@ -103,49 +273,226 @@ impl<State: Clone + Send + Sync + 'static> Middleware<State> for OpenTelemetryTr
         if let Some(ipaddr) = req.remote().and_then(|ipaddr| IpAddr::from_str(ipaddr).ok()) {
             attributes.push(trace::HTTP_CLIENT_IP.string(ipaddr.to_string()));
         }
+        attributes
+    }
 
-        let span_builder = self
-            .tracer
-            .span_builder(format!("{} {}", method, url))
-            .with_kind(SpanKind::Server)
-            .with_attributes(attributes);
-        let mut span = if parent_cx.span().span_context().is_remote() {
-            span_builder.start_with_context(&self.tracer, &parent_cx)
+    /// Records the response on the server span and injects the trace context into the response headers;
+    /// if the response body is tracked, a successful status is only set once the body has been sent
+    pub(crate) fn finish_span(&self, cx: &Context, res: &mut Response, body_tracked: bool) {
+        let span = cx.span();
+        if span.is_recording() {
+            span.add_event("request.completed".to_owned(), vec![]);
+
+            // a panic might have been caught by this or an inner middleware of this crate
+            if let Some(PanicMessage(message)) = res.ext::<PanicMessage>() {
+                span.add_event(
+                    "exception".to_owned(),
+                    vec![
+                        trace::EXCEPTION_TYPE.string("panic"),
+                        trace::EXCEPTION_MESSAGE.string(message.clone()),
+                    ],
+                );
+                span.set_status(StatusCode::Error, message.clone());
+            } else if !(body_tracked && span_status(res.status()) == StatusCode::Ok) {
+                // an Ok status cannot be overridden anymore, which a failing body still has to do
+                span.set_status(span_status(res.status()), "".to_string());
+            }
+            span.set_attribute(trace::HTTP_STATUS_CODE.i64(u16::from(res.status()).into()));
+
+            if let Some(len) = res.len().and_then(|len| i64::try_from(len).ok()) {
+                span.set_attribute(trace::HTTP_RESPONSE_CONTENT_LENGTH.i64(len));
+            }
+        }
+
+        // write trace info to response, so it can be picked up by downstream services
+        let mut injector = HeaderInjector::new(res);
+        match &self.config.inject_propagator {
+            Some(propagator) => propagator.inject_context(cx, &mut injector),
+            None => global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut injector)),
+        }
+
+        if let (Some(debug_trace), Some(DebugTraced)) = (&self.config.debug_trace, cx.get::<DebugTraced>()) {
+            let trace_id = span.span_context().trace_id().to_string();
+            if let Ok(value) = HeaderValue::from_bytes(trace_id.into_bytes()) {
+                res.insert_header(&debug_trace.trace_id_header, value);
+            }
+        }
+
+        // marks the point in time when the response headers are ready to be sent
+        if span.is_recording() {
+            span.add_event("request.finished".to_owned(), vec![]);
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for OpenTelemetryTracingMiddleware {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+        let method = req.method();
+        let mut route = ResolvedRoute::new(&req, self.route_template.as_deref());
+        let cx = &self.start_span(&req, &route, None);
+        route.pass_on(&mut req);
+        let guard = CancellationGuard::new(cx);
+
+        if self.config.track_request_body && req.len() != Some(0) {
+            let cx = cx.clone();
+            let body = req.take_body();
+            req.set_body(ObservedBody::wrap(body, move |stats, outcome| {
+                record_request_body(&cx, stats, outcome)
+            }));
+        }
+
+        // call next in the chain
+        let mut res = if self.config.catch_panics {
+            in_server_context(panic::catch_unwind(next.run(req)), cx)
+                .await
+                .unwrap_or_else(panic::panic_response)
         } else {
-            span_builder.start(&self.tracer)
+            in_server_context(next.run(req), cx).await
         };
-        span.add_event("request.started".to_owned(), vec![]);
-        let cx = &Context::current_with_span(span);
 
-        // call next in the chain
-        let mut res = next.run(req).with_context(cx.clone()).await;
+        guard.disarm();
+        let track_response_body = self.config.track_response_body && has_body(method, &res);
+        self.finish_span(cx, &mut res, track_response_body);
 
-        let span = cx.span();
-        span.add_event("request.completed".to_owned(), vec![]);
+        if track_response_body {
+            let cx = cx.clone();
+            let status = res.status();
+            let body = res.take_body();
+            res.set_body(ObservedBody::wrap(body, move |stats, outcome| {
+                end_with_body(&cx, status, stats, outcome, None)
+            }));
+        }
+        Ok(res)
+    }
+}
 
-        span.set_status(span_status(res.status()), "".to_string());
-        span.set_attribute(trace::HTTP_STATUS_CODE.i64(u16::from(res.status()).into()));
+/// Runs the future of the inner middlewares and the endpoint within the context of the server span;
+/// with the `tracing-interop` feature also within a `tracing` span which is a child of the server span,
+/// so spans of libraries instrumented with `tracing` nest under the request
+pub(crate) fn in_server_context<F: Future>(fut: F, cx: &Context) -> impl Future<Output = F::Output> {
+    let fut = fut.with_context(cx.clone());
+    #[cfg(feature = "tracing-interop")]
+    let fut = ::tracing::Instrument::instrument(fut, interop_span(cx));
+    fut
+}
 
-        if let Some(len) = res.len().and_then(|len| i64::try_from(len).ok()) {
-            span.set_attribute(trace::HTTP_RESPONSE_CONTENT_LENGTH.i64(len));
+#[cfg(feature = "tracing-interop")]
+fn interop_span(cx: &Context) -> ::tracing::Span {
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    let span = ::tracing::info_span!("tide.request", otel.kind = "internal");
+    span.set_parent(cx.clone());
+    span
+}
+
+/// Ends the server span as cancelled, if the request future gets dropped before completion;
+/// tide does that for example when the client disconnects
+struct CancellationGuard<'a> {
+    cx: &'a Context,
+    armed: bool,
+}
+
+impl<'a> CancellationGuard<'a> {
+    fn new(cx: &'a Context) -> Self {
+        Self { cx, armed: true }
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancellationGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            end_cancelled(self.cx, None);
         }
+    }
+}
 
-        // write trace info to response, so it can be picked up by downstream services
-        let mut injector = HashMap::new();
-        global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut injector));
-
-        for (k, v) in injector {
-            let header_name = HeaderName::from_bytes(k.clone().into_bytes());
-            let header_value = HeaderValue::from_bytes(v.clone().into_bytes());
-            if let (Ok(name), Ok(value)) = (header_name, header_value) {
-                res.insert_header(name, value);
-            } else {
-                log::error!("Could not compose header for pair: ({}, {})", k, v);
+/// Ends the server span of a request which did not complete;
+/// either due to a cancellation or an uncaught panic unwinding
+pub(crate) fn end_cancelled(cx: &Context, end_time: Option<SystemTime>) {
+    let span = cx.span();
+    // an uncaught panic unwinds through here, which is not a cancellation
+    if std::thread::panicking() {
+        span.set_status(StatusCode::Error, "request handler panicked".to_owned());
+    } else {
+        span.add_event("request.cancelled".to_owned(), vec![]);
+        span.set_attribute(HTTP_CANCELLED.bool(true));
+        span.set_status(StatusCode::Error, "request cancelled".to_owned());
+    }
+    end_span(&span, end_time);
+}
+
+/// Records how the request body was read by the handler
+pub(crate) fn record_request_body(cx: &Context, stats: BodyStats, outcome: BodyOutcome) {
+    // a body which was never touched is not worth reporting
+    let read_time = match stats.read_time {
+        Some(read_time) => read_time,
+        None => return,
+    };
+    let span = cx.span();
+    let mut attributes = Vec::with_capacity(3);
+    if let Ok(len) = i64::try_from(stats.bytes) {
+        attributes.push(trace::HTTP_REQUEST_CONTENT_LENGTH.i64(len));
+    }
+    attributes.push(HTTP_REQUEST_BODY_READ_TIME_MS.f64(read_time.as_secs_f64() * 1_000f64));
+    for attribute in &attributes {
+        span.set_attribute(attribute.clone());
+    }
+
+    let name = match outcome {
+        BodyOutcome::Completed => "request.body.finished",
+        BodyOutcome::Failed(message) => {
+            attributes.push(trace::EXCEPTION_MESSAGE.string(message));
+            "request.body.failed"
+        }
+        BodyOutcome::Dropped => "request.body.dropped",
+    };
+    span.add_event(name.to_owned(), attributes);
+}
+
+/// Ends the server span once the response body stream ended
+pub(crate) fn end_with_body(
+    cx: &Context,
+    status: tide::StatusCode,
+    stats: BodyStats,
+    outcome: BodyOutcome,
+    end_time: Option<SystemTime>,
+) {
+    let span = cx.span();
+    if let Ok(len) = i64::try_from(stats.bytes) {
+        span.set_attribute(trace::HTTP_RESPONSE_CONTENT_LENGTH.i64(len));
+    }
+    match outcome {
+        BodyOutcome::Completed => {
+            span.add_event("response.body.finished".to_owned(), vec![]);
+            if span_status(status) == StatusCode::Ok {
+                span.set_status(StatusCode::Ok, "".to_string());
             }
         }
+        BodyOutcome::Failed(message) => {
+            span.add_event(
+                "response.body.failed".to_owned(),
+                vec![trace::EXCEPTION_MESSAGE.string(message.clone())],
+            );
+            span.set_status(StatusCode::Error, message);
+        }
+        BodyOutcome::Dropped => {
+            span.add_event("response.body.cancelled".to_owned(), vec![]);
+            span.set_attribute(HTTP_CANCELLED.bool(true));
+            span.set_status(StatusCode::Error, "response body cancelled".to_owned());
+        }
+    }
+    end_span(&span, end_time);
+}
 
-        span.add_event("request.finished".to_owned(), vec![]);
-        Ok(res)
+#[inline]
+pub(crate) fn end_span(span: &SpanRef<'_>, end_time: Option<SystemTime>) {
+    match end_time {
+        Some(end_time) => span.end_with_timestamp(end_time),
+        None => span.end(),
     }
 }
 
@@ -0,0 +1,166 @@
+use opentelemetry::{
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+
+const CLOUD_TRACE_HEADER: &str = "x-cloud-trace-context";
+
+/**
+Propagator for the `X-Cloud-Trace-Context` header of Google Cloud,
+as set by Google Cloud load balancers, Cloud Run and App Engine
+
+The header looks like `105445aa7843bc8bf206b12000100000/1;o=1`, with the span id as decimal number
+and `o=1` for sampled traces.
+*/
+#[derive(Debug)]
+pub struct CloudTracePropagator {
+    fields: Vec<String>,
+}
+
+impl CloudTracePropagator {
+    /// Creates the propagator
+    pub fn new() -> Self {
+        Self {
+            fields: vec![CLOUD_TRACE_HEADER.to_owned()],
+        }
+    }
+
+    fn extract_span_context(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let (trace_id, rest) = extractor.get(CLOUD_TRACE_HEADER)?.trim().split_once('/')?;
+        let (span_id, options) = match rest.split_once(';') {
+            Some((span_id, options)) => (span_id, Some(options)),
+            None => (rest, None),
+        };
+        let span_id = span_id.parse::<u64>().ok().filter(|span_id| *span_id != 0)?;
+        let trace_flags = if options.map(str::trim) == Some("o=1") {
+            TraceFlags::SAMPLED
+        } else {
+            TraceFlags::default()
+        };
+        Some(SpanContext::new(
+            parse_trace_id(trace_id)?,
+            SpanId::from_bytes(span_id.to_be_bytes()),
+            trace_flags,
+            true,
+            TraceState::default(),
+        ))
+    }
+}
+
+fn parse_trace_id(hex: &str) -> Option<TraceId> {
+    if hex.len() == 32 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        TraceId::from_hex(hex)
+            .ok()
+            .filter(|trace_id| *trace_id != TraceId::INVALID)
+    } else {
+        None
+    }
+}
+
+impl Default for CloudTracePropagator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextMapPropagator for CloudTracePropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        injector.set(
+            CLOUD_TRACE_HEADER,
+            format!(
+                "{}/{};o={}",
+                span_context.trace_id(),
+                u64::from_be_bytes(span_context.span_id().to_bytes()),
+                if span_context.is_sampled() { 1 } else { 0 }
+            ),
+        );
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        match self.extract_span_context(extractor) {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const TRACE_ID: &str = "105445aa7843bc8bf206b12000100000";
+
+    fn extract(value: &str) -> SpanContext {
+        let headers: HashMap<String, String> = [(CLOUD_TRACE_HEADER.to_owned(), value.to_owned())].into();
+        let cx = CloudTracePropagator::new().extract(&headers);
+        let span = cx.span();
+        span.span_context().clone()
+    }
+
+    fn inject(span_context: SpanContext) -> Option<String> {
+        let cx = Context::new().with_remote_span_context(span_context);
+        let mut headers = HashMap::new();
+        CloudTracePropagator::new().inject_context(&cx, &mut headers);
+        headers.remove(CLOUD_TRACE_HEADER)
+    }
+
+    #[test]
+    fn span_id_is_decimal() {
+        let span_context = extract(&format!("{}/1;o=1", TRACE_ID));
+        assert!(span_context.is_remote());
+        assert_eq!(span_context.trace_id().to_string(), TRACE_ID);
+        assert_eq!(span_context.span_id().to_string(), "0000000000000001");
+
+        let span_context = extract(&format!("{}/18446744073709551615;o=1", TRACE_ID));
+        assert_eq!(span_context.span_id().to_string(), "ffffffffffffffff");
+    }
+
+    #[test]
+    fn option_tells_whether_sampled() {
+        assert!(extract(&format!("{}/1;o=1", TRACE_ID)).is_sampled());
+        assert!(!extract(&format!("{}/1;o=0", TRACE_ID)).is_sampled());
+        assert!(!extract(&format!("{}/1", TRACE_ID)).is_sampled());
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for value in [
+            "",
+            TRACE_ID,
+            &format!("{}/0;o=1", TRACE_ID),
+            &format!("{}/abc;o=1", TRACE_ID),
+            &format!("{}/-1;o=1", TRACE_ID),
+            "105445aa7843bc8b/1;o=1",
+            "00000000000000000000000000000000/1;o=1",
+            "105445aa7843bc8bf206b1200010000g/1;o=1",
+        ] {
+            assert!(!extract(value).is_valid(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn injected_header_round_trips() {
+        for value in [
+            format!("{}/12345;o=1", TRACE_ID),
+            format!("{}/18446744073709551615;o=0", TRACE_ID),
+        ] {
+            assert_eq!(inject(extract(&value)).as_deref(), Some(value.as_str()));
+        }
+    }
+
+    #[test]
+    fn nothing_is_injected_without_a_span() {
+        assert_eq!(inject(SpanContext::empty_context()), None);
+    }
+}
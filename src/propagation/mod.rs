@@ -0,0 +1,188 @@
+//! Trace context propagation over the headers of tide and http-types requests and responses,
+//! and propagators for the common trace header formats, selectable by name
+
+use kv_log_macro as log;
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    sdk::propagation::{BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator},
+    trace::TraceError,
+};
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+use tide::http::headers::{HeaderName, HeaderValue, HeaderValues, Headers};
+
+#[cfg(feature = "propagator-gcp")]
+mod cloud_trace;
+
+#[cfg(feature = "propagator-gcp")]
+pub use cloud_trace::CloudTracePropagator;
+
+/**
+Creates the propagator of the given name, or `None` if it is unknown or its feature is not enabled
+
+| name | propagator | feature |
+| :--- | :--------- | :------ |
+| `tracecontext` | W3C `traceparent` and `tracestate` | |
+| `baggage` | W3C `baggage` | |
+| `b3` | B3 single header (`b3`), injected along with the multiple headers | `propagator-b3` |
+| `b3multi` | B3 multiple headers (`X-B3-TraceId`, …) | `propagator-b3` |
+| `jaeger` | Jaeger / Uber (`uber-trace-id`) | `propagator-jaeger` |
+| `xray` | AWS X-Ray (`X-Amzn-Trace-Id`) | `propagator-xray` |
+| `cloudtrace` | Google Cloud (`X-Cloud-Trace-Context`) | `propagator-gcp` |
+
+The names follow the values of the `OTEL_PROPAGATORS` environment variable, as far as they are specified there.
+The B3, Jaeger and X-Ray propagators are those of the `opentelemetry-zipkin`, `opentelemetry-jaeger`
+and `opentelemetry-aws` crates.
+*/
+pub fn by_name(name: &str) -> Option<Box<dyn TextMapPropagator + Send + Sync>> {
+    match name {
+        "tracecontext" => Some(Box::new(TraceContextPropagator::new())),
+        "baggage" => Some(Box::new(BaggagePropagator::new())),
+        #[cfg(feature = "propagator-b3")]
+        "b3" => Some(Box::new(opentelemetry_zipkin::Propagator::with_encoding(
+            opentelemetry_zipkin::B3Encoding::SingleHeader,
+        ))),
+        #[cfg(feature = "propagator-b3")]
+        "b3multi" => Some(Box::new(opentelemetry_zipkin::Propagator::with_encoding(
+            opentelemetry_zipkin::B3Encoding::MultipleHeader,
+        ))),
+        #[cfg(feature = "propagator-jaeger")]
+        "jaeger" => Some(Box::new(opentelemetry_jaeger::Propagator::new())),
+        #[cfg(feature = "propagator-xray")]
+        "xray" => Some(Box::new(opentelemetry_aws::XrayPropagator::new())),
+        #[cfg(feature = "propagator-gcp")]
+        "cloudtrace" => Some(Box::new(CloudTracePropagator::new())),
+        _ => None,
+    }
+}
+
+/**
+Creates a composite propagator from a comma separated list of names (see [by_name]),
+ready for [crate::TracingConfig::extract_propagator] and [crate::TracingConfig::inject_propagator]
+
+Fails on unknown names (or names whose feature is not enabled).
+
+# Examples
+
+```rust,no_run
+let config = opentelemetry_tide::TracingConfig {
+    extract_propagator: Some(opentelemetry_tide::propagation::by_names("tracecontext,b3multi").unwrap()),
+    ..Default::default()
+};
+```
+*/
+pub fn by_names(names: &str) -> Result<Arc<dyn TextMapPropagator + Send + Sync>, TraceError> {
+    let propagators = names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| by_name(name).ok_or_else(|| TraceError::from(format!("unknown propagator {:?}", name))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Arc::new(TextMapCompositePropagator::new(propagators)))
+}
+
+/**
+An [Extractor] reading the headers of a request (or response) in place, without copying them
+
+Works with everything which has headers, like `tide::Request`, `tide::Response`, `surf::Response` or `http_types::Request`.
+Header names are matched case-insensitively. Headers with multiple values (like several `tracestate` or `baggage` lines)
+are joined with commas, as a list header would be sent on a single line; single values are returned as they are.
+
+# Examples
+
+```rust,no_run
+use opentelemetry::global;
+use opentelemetry_tide::propagation::HeaderExtractor;
+
+async fn handler(req: tide::Request<()>) -> tide::Result {
+    let parent_cx = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor::new(&req)));
+    Ok("".into())
+}
+```
+*/
+#[derive(Debug)]
+pub struct HeaderExtractor<'a> {
+    headers: &'a Headers,
+    // only the multi-valued headers are joined, when they are looked up, and only those need an allocation
+    joined: Vec<(&'a HeaderName, OnceLock<String>)>,
+}
+
+impl<'a> HeaderExtractor<'a> {
+    /// Wraps the headers of the given request or response
+    pub fn new<H: AsRef<Headers>>(headers: &'a H) -> Self {
+        let headers = headers.as_ref();
+        let joined = headers
+            .iter()
+            .filter(|(_, values)| values.get(1).is_some())
+            .map(|(name, _)| (name, OnceLock::new()))
+            .collect();
+        Self { headers, joined }
+    }
+}
+
+fn join(values: &HeaderValues) -> String {
+    values.iter().map(HeaderValue::as_str).collect::<Vec<_>>().join(",")
+}
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        let (name, values) = self
+            .headers
+            .iter()
+            .find(|(name, _)| name.as_str().eq_ignore_ascii_case(key))?;
+        match self.joined.iter().find(|(joined, _)| *joined == name) {
+            Some((_, joined)) => Some(joined.get_or_init(|| join(values)).as_str()),
+            None => Some(values.last().as_str()),
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+}
+
+/**
+An [Injector] writing into the headers of a response (or request) directly
+
+Works with everything which has mutable headers, like `tide::Response`, `surf::Request` or `http_types::Request`.
+An injected header replaces all previous values of it; pairs which are no valid header are logged and skipped.
+
+# Examples
+
+```rust,no_run
+use opentelemetry::{global, Context};
+use opentelemetry_tide::propagation::HeaderInjector;
+
+let mut upstream_req = surf::get("http://localhost:3000/").build();
+global::get_text_map_propagator(|propagator| {
+    propagator.inject_context(&Context::current(), &mut HeaderInjector::new(&mut upstream_req))
+});
+```
+*/
+#[derive(Debug)]
+pub struct HeaderInjector<'a> {
+    headers: &'a mut Headers,
+}
+
+impl<'a> HeaderInjector<'a> {
+    /// Wraps the headers of the given response or request
+    pub fn new<H: AsMut<Headers>>(headers: &'a mut H) -> Self {
+        Self {
+            headers: headers.as_mut(),
+        }
+    }
+}
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        let header_name = HeaderName::from_str(key);
+        let header_value = HeaderValue::from_str(&value);
+        if let (Ok(name), Ok(value)) = (header_name, header_value) {
+            let _ = self.headers.insert(name, value);
+        } else {
+            log::error!("Could not compose header for pair: ({}, {})", key, value);
+        }
+    }
+}
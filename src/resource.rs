@@ -0,0 +1,275 @@
+//! Resource detectors for the identity of the service: service, host, process, container and Kubernetes attributes
+//!
+//! Use [`detect_resource!`](crate::detect_resource) to get all of them at once,
+//! with the service name and version of your crate, for both the tracer and the meter:
+//!
+//! ```rust,no_run
+//! let resource = opentelemetry_tide::detect_resource!();
+//!
+//! let trace_config = opentelemetry::sdk::trace::config().with_resource(resource.clone());
+//! let metrics_config = opentelemetry_tide::MetricsConfig {
+//!     resource: Some(resource),
+//!     ..Default::default()
+//! };
+//! ```
+
+use opentelemetry::{
+    sdk::{
+        resource::{EnvResourceDetector, ResourceDetector},
+        Resource,
+    },
+    KeyValue,
+};
+use opentelemetry_semantic_conventions::resource as semconv;
+use std::{env, fs, time::Duration};
+
+const OTEL_SERVICE_NAME: &str = "OTEL_SERVICE_NAME";
+const UNKNOWN_SERVICE: &str = "unknown_service";
+
+// file-based detectors do not block, so no timeout is needed
+const NO_TIMEOUT: Duration = Duration::from_secs(0);
+
+/// Runs all detectors of this module and the ones for the `OTEL_*` environment variables
+///
+/// The later sources take precedence, from lowest to highest:
+/// the given service name and version, host, process, container, Kubernetes,
+/// `OTEL_RESOURCE_ATTRIBUTES`, and finally `OTEL_SERVICE_NAME`.
+///
+/// Prefer the [`detect_resource!`](crate::detect_resource) macro,
+/// which passes in the name and version of your crate.
+pub fn detect(service_name: Option<&str>, service_version: Option<&str>) -> Resource {
+    let detectors: Vec<Box<dyn ResourceDetector>> = vec![
+        Box::new(ServiceResourceDetector::new(service_name, service_version)),
+        Box::new(HostResourceDetector),
+        Box::new(ProcessResourceDetector),
+        Box::new(ContainerResourceDetector),
+        Box::new(KubernetesResourceDetector),
+        Box::new(EnvResourceDetector::new()),
+    ];
+    let resource = Resource::from_detectors(NO_TIMEOUT, detectors);
+
+    let service_name = match env::var(OTEL_SERVICE_NAME) {
+        Ok(name) if !name.is_empty() => name,
+        _ if resource.get(semconv::SERVICE_NAME).is_some() => return resource,
+        _ => UNKNOWN_SERVICE.to_owned(),
+    };
+    resource.merge(&Resource::new(vec![semconv::SERVICE_NAME.string(service_name)]))
+}
+
+/**
+Detects the resource of your service, see [`resource::detect`](crate::resource::detect)
+
+The service name and version are taken from the Cargo package of the crate the macro is used in.
+*/
+#[macro_export]
+macro_rules! detect_resource {
+    () => {
+        $crate::resource::detect(Some(env!("CARGO_PKG_NAME")), Some(env!("CARGO_PKG_VERSION")))
+    };
+}
+
+/// Provides `service.name` and `service.version`, usually from the Cargo package
+#[derive(Debug, Clone, Default)]
+pub struct ServiceResourceDetector {
+    name: Option<String>,
+    version: Option<String>,
+}
+
+impl ServiceResourceDetector {
+    /// Creates the detector for the given service name and version
+    pub fn new(name: Option<&str>, version: Option<&str>) -> Self {
+        Self {
+            name: name.map(ToOwned::to_owned),
+            version: version.map(ToOwned::to_owned),
+        }
+    }
+}
+
+impl ResourceDetector for ServiceResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let name = self.name.clone().map(|name| semconv::SERVICE_NAME.string(name));
+        let version = self
+            .version
+            .clone()
+            .map(|version| semconv::SERVICE_VERSION.string(version));
+        Resource::new(name.into_iter().chain(version))
+    }
+}
+
+/// Provides `host.name`, from the `HOSTNAME` environment variable or the kernel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostResourceDetector;
+
+impl ResourceDetector for HostResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let hostname = env::var("HOSTNAME")
+            .ok()
+            .or_else(|| read_trimmed("/proc/sys/kernel/hostname"))
+            .or_else(|| read_trimmed("/etc/hostname"))
+            .filter(|hostname| !hostname.is_empty());
+        Resource::new(hostname.map(|hostname| semconv::HOST_NAME.string(hostname)))
+    }
+}
+
+/// Provides `process.pid`, `process.executable.name` and `process.executable.path`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessResourceDetector;
+
+impl ResourceDetector for ProcessResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let mut attributes = vec![semconv::PROCESS_PID.i64(i64::from(std::process::id()))];
+        if let Ok(path) = env::current_exe() {
+            if let Some(name) = path.file_name() {
+                attributes.push(semconv::PROCESS_EXECUTABLE_NAME.string(name.to_string_lossy().into_owned()));
+            }
+            attributes.push(semconv::PROCESS_EXECUTABLE_PATH.string(path.display().to_string()));
+        }
+        Resource::new(attributes)
+    }
+}
+
+/// Provides `container.id`, from the cgroup (v1) or the mounts (cgroup v2) of the process
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContainerResourceDetector;
+
+impl ResourceDetector for ContainerResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let container_id = read_trimmed("/proc/self/cgroup")
+            .and_then(|cgroup| container_id_from_cgroup(&cgroup))
+            .or_else(|| read_trimmed("/proc/self/mountinfo").and_then(|mounts| container_id_from_mountinfo(&mounts)));
+        Resource::new(container_id.map(|id| semconv::CONTAINER_ID.string(id)))
+    }
+}
+
+/// Provides `k8s.pod.name`, `k8s.namespace.name` and `k8s.node.name`
+/// from environment variables set via the downward API
+///
+/// Both the `K8S_POD_NAME`, `K8S_NAMESPACE_NAME`, `K8S_NODE_NAME`
+/// and the `POD_NAME`, `POD_NAMESPACE`, `NODE_NAME` naming schemes are supported, for example:
+///
+/// ```yaml
+/// env:
+///   - name: K8S_POD_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.name
+///   - name: K8S_NAMESPACE_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.namespace
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KubernetesResourceDetector;
+
+impl ResourceDetector for KubernetesResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let sources = [
+            (semconv::K8S_POD_NAME, ["K8S_POD_NAME", "POD_NAME"]),
+            (semconv::K8S_NAMESPACE_NAME, ["K8S_NAMESPACE_NAME", "POD_NAMESPACE"]),
+            (semconv::K8S_NODE_NAME, ["K8S_NODE_NAME", "NODE_NAME"]),
+        ];
+        Resource::new(sources.iter().filter_map(|(key, vars)| {
+            vars.iter()
+                .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+                .map(|value| KeyValue::new(key.clone(), value))
+        }))
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|content| content.trim().to_owned())
+}
+
+// container runtimes use the 64 hex chars long id as (part of) the last path segment,
+// like `/docker/<id>`, `/kubepods/.../<id>` or `/system.slice/docker-<id>.scope`
+fn container_id_from_cgroup(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.rsplit('/').next())
+        .find_map(container_id_from_segment)
+}
+
+// with cgroup v2 the cgroup path is usually just `/`, but the runtime mounts files from the container's directory,
+// like `/var/lib/docker/containers/<id>/hostname`
+fn container_id_from_mountinfo(mounts: &str) -> Option<String> {
+    mounts
+        .lines()
+        .flat_map(|line| line.split_whitespace())
+        .filter(|field| field.contains("/containers/"))
+        .flat_map(|path| path.split('/'))
+        .find_map(container_id_from_segment)
+}
+
+fn container_id_from_segment(segment: &str) -> Option<String> {
+    let segment = segment.trim_end_matches(".scope");
+    let candidate = segment.rsplit(['-', ':']).next()?;
+    if candidate.len() == 64 && candidate.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(candidate.to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f4a8c2b9d1e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a";
+
+    #[test]
+    fn container_id_from_cgroup_v1() {
+        let docker = format!("12:memory:/docker/{}\n11:cpu:/docker/{}", ID, ID);
+        assert_eq!(container_id_from_cgroup(&docker), Some(ID.to_owned()));
+
+        let kubepods = format!("1:name=systemd:/kubepods/burstable/pod1234/{}", ID);
+        assert_eq!(container_id_from_cgroup(&kubepods), Some(ID.to_owned()));
+
+        let systemd = format!("0::/system.slice/docker-{}.scope", ID);
+        assert_eq!(container_id_from_cgroup(&systemd), Some(ID.to_owned()));
+
+        let containerd = format!("0::/kubepods.slice/cri-containerd:{}", ID);
+        assert_eq!(container_id_from_cgroup(&containerd), Some(ID.to_owned()));
+    }
+
+    #[test]
+    fn no_container_id_outside_of_containers() {
+        assert_eq!(container_id_from_cgroup("0::/"), None);
+        assert_eq!(
+            container_id_from_cgroup("0::/user.slice/user-1000.slice/session-2.scope"),
+            None
+        );
+        // one character short
+        assert_eq!(container_id_from_cgroup(&format!("0::/docker/{}", &ID[1..])), None);
+    }
+
+    #[test]
+    fn container_id_from_cgroup_v2_mounts() {
+        let mounts = format!(
+            "1 0 0:1 / / rw - overlay overlay rw\n\
+             2 1 254:1 /var/lib/docker/containers/{}/hostname /etc/hostname rw - ext4 /dev/vda1 rw",
+            ID
+        );
+        assert_eq!(container_id_from_mountinfo(&mounts), Some(ID.to_owned()));
+        assert_eq!(container_id_from_mountinfo("1 0 0:1 / / rw - overlay overlay rw"), None);
+    }
+
+    #[test]
+    fn service_detector_only_provides_known_values() {
+        let resource = ServiceResourceDetector::new(Some("shop"), Some("1.2.3")).detect(NO_TIMEOUT);
+        assert_eq!(resource.get(semconv::SERVICE_NAME), Some("shop".into()));
+        assert_eq!(resource.get(semconv::SERVICE_VERSION), Some("1.2.3".into()));
+
+        let resource = ServiceResourceDetector::new(None, None).detect(NO_TIMEOUT);
+        assert!(resource.is_empty());
+    }
+
+    #[test]
+    fn process_detector_provides_the_pid() {
+        let resource = ProcessResourceDetector.detect(NO_TIMEOUT);
+        assert_eq!(
+            resource.get(semconv::PROCESS_PID),
+            Some(i64::from(std::process::id()).into())
+        );
+        assert!(resource.get(semconv::PROCESS_EXECUTABLE_NAME).is_some());
+    }
+}
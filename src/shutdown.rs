@@ -0,0 +1,231 @@
+//! Serving a tide app until a termination signal, without losing the last spans and metrics
+
+use crate::middlewares::body::{has_body, ObservedBody};
+use async_std::{channel, future, task};
+use futures_util::future::{select, Either};
+use kv_log_macro as log;
+use opentelemetry::global;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tide::{
+    http::headers::CONNECTION,
+    listener::{Listener, ToListener},
+    Middleware, Next, Request, Response, Server, StatusCode,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(feature = "metrics")]
+type MetricsSink = Box<dyn FnOnce(String) + Send + Sync + 'static>;
+
+/// Configuration for the graceful shutdown
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct ShutdownConfig {
+    /// How long to wait for in-flight requests (including the sending of their response bodies)
+    /// after the listener stopped accepting new connections
+    pub timeout: Duration,
+    /// Receives a final export of the metrics (in the prometheus text format) after the requests were drained,
+    /// e.g. to push them to a pushgateway, as the scraping route is not reachable anymore at that point;
+    /// without a sink the final export gets logged, if there is a metrics middleware
+    #[cfg(feature = "metrics")]
+    pub metrics_sink: Option<MetricsSink>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            #[cfg(feature = "metrics")]
+            metrics_sink: None,
+        }
+    }
+}
+
+impl fmt::Debug for ShutdownConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ShutdownConfig");
+        let _ = debug.field("timeout", &self.timeout);
+        #[cfg(feature = "metrics")]
+        let _ = debug.field("metrics_sink", &self.metrics_sink.as_ref().map(|_| "FnOnce(String)"));
+        debug.finish()
+    }
+}
+
+/**
+Serves the app until the process receives SIGINT or SIGTERM (Ctrl-C on Windows), then shuts down gracefully
+
+After the signal the listener stops accepting connections,
+the requests in flight get up to [`ShutdownConfig::timeout`] to finish,
+and finally the traces get flushed and the metrics get a final export.
+Requests still arriving on open (keep-alive) connections are rejected with `503 Service Unavailable`,
+and all responses from then on close their connection.
+
+The termination handler can only be installed once per process;
+use [`listen_until`] to shut down on your own signal instead.
+
+# Examples
+
+```rust,no_run
+use opentelemetry_tide::TideExt;
+
+#[async_std::main]
+async fn main() -> std::io::Result<()> {
+    let mut app = tide::new();
+    app.with_default_middlewares();
+    app.at("/").get(|_| async { Ok("Hello!") });
+    opentelemetry_tide::shutdown::listen_with_shutdown(app, "0.0.0.0:3000", Default::default()).await
+}
+```
+*/
+pub async fn listen_with_shutdown<State, L>(app: Server<State>, listener: L, config: ShutdownConfig) -> io::Result<()>
+where
+    State: Clone + Send + Sync + 'static,
+    L: ToListener<State>,
+{
+    listen_until(app, listener, termination_signal()?, config).await
+}
+
+/**
+Serves the app until the given future resolves, then shuts down gracefully like [`listen_with_shutdown`]
+
+To serve on a port picked by the operating system, pass a listener bound to port 0,
+like a `std::net::TcpListener`, whose local address tells the port.
+*/
+pub async fn listen_until<State, L, S>(
+    app: Server<State>,
+    listener: L,
+    signal: S,
+    config: ShutdownConfig,
+) -> io::Result<()>
+where
+    State: Clone + Send + Sync + 'static,
+    L: ToListener<State>,
+    S: Future<Output = ()> + Send,
+{
+    // the app gets nested into a server of its own, so requests are counted before any middleware of the app runs
+    let in_flight = InFlight::default();
+    let mut server = Server::with_state(app.state().clone());
+    let _ = server.with(in_flight.clone());
+    let _ = server.at("/").all(app.clone());
+    let _ = server.at("*").all(app);
+
+    let mut listener = server.bind(listener).await?;
+    for info in listener.info().iter() {
+        log::info!("Server listening on {}", info);
+    }
+
+    let accept = Box::pin(listener.accept());
+    let signal = Box::pin(signal);
+    if let Either::Left((result, _)) = select(accept, signal).await {
+        // the listener stopped by itself, nothing left to drain
+        result?;
+    }
+    drop(listener);
+    in_flight.shut_down();
+
+    log::info!("Shutting down, waiting for {} requests in flight", in_flight.count());
+    if future::timeout(config.timeout, in_flight.drained()).await.is_err() {
+        log::warn!(
+            "Shutdown timeout of {:?} elapsed with {} requests still in flight",
+            config.timeout,
+            in_flight.count()
+        );
+    }
+
+    global::force_flush_tracer_provider();
+    global::shutdown_tracer_provider();
+
+    #[cfg(feature = "metrics")]
+    match (crate::middlewares::metrics::export_installed(), config.metrics_sink) {
+        (Some(Ok(metrics)), Some(sink)) => sink(metrics),
+        (Some(Ok(metrics)), None) => log::info!("Final metrics export", { metrics: metrics }),
+        (Some(Err(error)), _) => log::error!("Final metrics export failed: {}", error),
+        // only worth a warning if the final export was asked for; a trace-only app has nothing to export
+        (None, Some(_)) => log::warn!("No metrics middleware installed, skipping the final metrics export"),
+        (None, None) => {}
+    }
+
+    Ok(())
+}
+
+fn termination_signal() -> io::Result<impl Future<Output = ()> + Send> {
+    let (sender, receiver) = channel::bounded(1);
+    ctrlc::set_handler(move || {
+        let _ = sender.try_send(());
+    })
+    .map_err(io::Error::other)?;
+    Ok(async move {
+        let _ = receiver.recv().await;
+    })
+}
+
+/// Counts the requests which are in flight, until their response body has been sent,
+/// and turns away new requests once the shutdown started
+#[derive(Debug, Clone, Default)]
+struct InFlight {
+    count: Arc<AtomicUsize>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl InFlight {
+    fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    async fn drained(&self) {
+        while self.count() > 0 {
+            task::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+}
+
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for InFlight {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        // counted before checking for the shutdown, so the draining cannot miss a request which got through
+        let _ = self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.count.clone());
+        if self.is_shutting_down() {
+            let mut res = Response::new(StatusCode::ServiceUnavailable);
+            res.insert_header(CONNECTION, "close");
+            return Ok(res);
+        }
+
+        let method = req.method();
+        let mut res = next.run(req).await;
+        // the listener only stopped accepting new connections, keep-alive connections have to be closed as well
+        if self.is_shutting_down() {
+            res.insert_header(CONNECTION, "close");
+        }
+        if has_body(method, &res) {
+            let body = res.take_body();
+            res.set_body(ObservedBody::wrap(body, move |_, _| drop(guard)));
+        }
+        Ok(res)
+    }
+}
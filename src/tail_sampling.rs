@@ -0,0 +1,583 @@
+//! Tail sampling, deciding about whole local traces of a request when its server span ends
+
+use crate::middlewares::sampling::route_matches;
+use opentelemetry::{
+    sdk::{
+        export::trace::SpanData,
+        trace::{Sampler, SamplingDecision, ShouldSample, Span, SpanProcessor},
+        InstrumentationLibrary,
+    },
+    trace::{Span as _, SpanId, SpanKind, StatusCode, TraceContextExt, TraceId, TraceResult},
+    Context,
+};
+use opentelemetry_semantic_conventions::trace;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+/**
+Configuration for the tail sampling span processor
+
+Unless you need specific values, [TailSamplingConfig::default()] keeps all traces with errors or slower than a second,
+and 1% of the others.
+*/
+#[derive(Debug, Clone)]
+// cannot use #[non_exhaustive] if we want to allow struct expression construction
+pub struct TailSamplingConfig {
+    /// Keep traces with a span whose status is error
+    pub keep_errors: bool,
+    /// Keep traces whose local root span took longer than this
+    pub latency_threshold: Option<Duration>,
+    /// Keep traces whose local root span has a matching route (`http.route`, or the path of `http.target`);
+    /// a route is matched exactly, or, with a trailing `*`, as a prefix
+    pub routes: Vec<String>,
+    /// The ratio (between `0.0` and `1.0`) of the other traces to keep, decided by the trace id
+    pub base_ratio: f64,
+    /// The maximum number of traces buffered at a time;
+    /// if exceeded, the oldest trace gets decided early, with what is known about it so far.
+    /// As many decisions are remembered for the spans ending after them;
+    /// spans of traces whose decision has been forgotten are kept
+    pub max_traces: usize,
+    /// The maximum number of spans buffered per trace; further spans of the trace are dropped.
+    /// A slot is reserved for each open local root span, so the roots are always kept and the kept spans are not orphaned
+    pub max_spans_per_trace: usize,
+}
+
+impl Default for TailSamplingConfig {
+    fn default() -> Self {
+        Self {
+            keep_errors: true,
+            latency_threshold: Some(Duration::from_secs(1)),
+            routes: Vec::new(),
+            base_ratio: 0.01,
+            max_traces: 1024,
+            max_spans_per_trace: 256,
+        }
+    }
+}
+
+/**
+A span processor which buffers the spans of a trace until its local root span (usually the server span of a request)
+has ended, and then passes them on to the wrapped processor if the trace is worth keeping, or drops them otherwise
+
+A trace is kept if a span has an error status, the local root span was slow or has a matching route,
+or else by chance with the base ratio (see [TailSamplingConfig]). Spans ending after the decision follow it.
+
+The decision is moved from the start to the end of the request,
+so the tracer provider has to record every span: configure it with `Sampler::AlwaysOn`.
+Mind that the propagated trace context is then always sampled, so downstream services record their spans as well.
+
+# Examples
+
+```rust,no_run
+use opentelemetry::sdk::trace::{self, BatchSpanProcessor, Sampler, TracerProvider};
+use opentelemetry_tide::tail_sampling::{TailSamplingConfig, TailSamplingProcessor};
+
+let exporter = opentelemetry_jaeger::new_pipeline().init_async_exporter(opentelemetry::runtime::AsyncStd).unwrap();
+let batch = BatchSpanProcessor::builder(exporter, opentelemetry::runtime::AsyncStd).build();
+let config = TailSamplingConfig {
+    routes: vec!["/checkout".into()],
+    ..Default::default()
+};
+let provider = TracerProvider::builder()
+    .with_config(trace::config().with_sampler(Sampler::AlwaysOn))
+    .with_span_processor(TailSamplingProcessor::new(batch, config))
+    .build();
+let _ = opentelemetry::global::set_tracer_provider(provider);
+```
+*/
+pub struct TailSamplingProcessor {
+    inner: Box<dyn SpanProcessor>,
+    config: TailSamplingConfig,
+    state: Mutex<State>,
+}
+
+impl fmt::Debug for TailSamplingProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TailSamplingProcessor")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// The spans of a trace which has not been decided yet
+#[derive(Debug, Default)]
+struct TraceBuffer {
+    spans: Vec<SpanData>,
+    // local root spans which have not ended yet; there may be several, like for retries within a trace
+    open_roots: Vec<SpanId>,
+    keep: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    traces: HashMap<TraceId, TraceBuffer>,
+    // insertion order of the buffered traces, for the eviction of the oldest; may contain decided ones
+    trace_order: VecDeque<TraceId>,
+    decisions: HashMap<TraceId, bool>,
+    decision_order: VecDeque<TraceId>,
+}
+
+impl State {
+    fn remember(&mut self, trace_id: TraceId, keep: bool, capacity: usize) {
+        if self.decisions.insert(trace_id, keep).is_none() {
+            self.decision_order.push_back(trace_id);
+        }
+        while self.decision_order.len() > capacity {
+            if let Some(oldest) = self.decision_order.pop_front() {
+                let _ = self.decisions.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl TailSamplingProcessor {
+    /// Wraps the processor which receives the spans of the kept traces, like a `BatchSpanProcessor`
+    pub fn new(inner: impl SpanProcessor + 'static, config: TailSamplingConfig) -> Self {
+        Self {
+            inner: Box::new(inner),
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Whether the local root span alone makes the trace worth keeping
+    fn is_interesting(&self, root: &SpanData) -> bool {
+        let slow = self.config.latency_threshold.is_some_and(|threshold| {
+            root.end_time
+                .duration_since(root.start_time)
+                .is_ok_and(|duration| duration > threshold)
+        });
+        slow || (!self.config.routes.is_empty() && self.matches_route(root))
+    }
+
+    fn matches_route(&self, root: &SpanData) -> bool {
+        let attributes = &root.attributes;
+        match attributes
+            .get(&trace::HTTP_ROUTE)
+            .or_else(|| attributes.get(&trace::HTTP_TARGET))
+        {
+            Some(route) => {
+                let route = route.as_str();
+                let path = route.split('?').next().unwrap_or_default();
+                self.config.routes.iter().any(|pattern| route_matches(pattern, path))
+            }
+            None => false,
+        }
+    }
+
+    fn by_chance(&self, trace_id: TraceId) -> bool {
+        let sampler = Sampler::TraceIdRatioBased(self.config.base_ratio);
+        let library = InstrumentationLibrary::default();
+        let result = sampler.should_sample(None, trace_id, "", &SpanKind::Internal, &[], &[], &library);
+        result.decision == SamplingDecision::RecordAndSample
+    }
+
+    /// Decides about a buffered trace and returns its spans if it is kept
+    fn decide(&self, state: &mut State, trace_id: TraceId) -> Vec<SpanData> {
+        let buffer = match state.traces.remove(&trace_id) {
+            Some(buffer) => buffer,
+            None => return Vec::new(),
+        };
+        let keep = buffer.keep || self.by_chance(trace_id);
+        state.remember(trace_id, keep, self.config.max_traces);
+        if keep {
+            buffer.spans
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Decides about the oldest buffered traces, until there is room for another one
+    fn evict(&self, state: &mut State) -> Vec<SpanData> {
+        let mut spans = Vec::new();
+        while state.traces.len() >= self.config.max_traces.max(1) {
+            match state.trace_order.pop_front() {
+                Some(trace_id) => spans.append(&mut self.decide(state, trace_id)),
+                None => break,
+            }
+        }
+        spans
+    }
+
+    fn export(&self, spans: Vec<SpanData>) {
+        for span in spans {
+            self.inner.on_end(span);
+        }
+    }
+}
+
+impl SpanProcessor for TailSamplingProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        let parent_span = cx.span();
+        let parent = parent_span.span_context();
+        if !parent.is_valid() || parent.is_remote() {
+            let span_context = span.span_context();
+            let (trace_id, span_id) = (span_context.trace_id(), span_context.span_id());
+            let mut state = self.state();
+            let evicted = if state.traces.contains_key(&trace_id) {
+                Vec::new()
+            } else {
+                let evicted = self.evict(&mut state);
+                state.trace_order.push_back(trace_id);
+                if state.trace_order.len() > 2 * self.config.max_traces {
+                    let State {
+                        traces, trace_order, ..
+                    } = &mut *state;
+                    trace_order.retain(|trace_id| traces.contains_key(trace_id));
+                }
+                evicted
+            };
+            state.traces.entry(trace_id).or_default().open_roots.push(span_id);
+            drop(state);
+            self.export(evicted);
+        }
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let trace_id = span.span_context.trace_id();
+        let mut state = self.state();
+
+        let buffer = match state.traces.get_mut(&trace_id) {
+            Some(buffer) => buffer,
+            None => {
+                // decided already, or started before this processor was installed;
+                // only the last decisions are remembered (as many as `max_traces`), so spans ending long after
+                // the decision about their trace (or of traces started before the installation) are kept,
+                // rather than losing parts of kept traces
+                let keep = state.decisions.get(&trace_id).copied().unwrap_or(true);
+                drop(state);
+                if keep {
+                    self.inner.on_end(span);
+                }
+                return;
+            }
+        };
+
+        if self.config.keep_errors && span.status_code == StatusCode::Error {
+            buffer.keep = true;
+        }
+        let span_id = span.span_context.span_id();
+        let is_root = match buffer.open_roots.iter().position(|root| *root == span_id) {
+            Some(index) => {
+                let _ = buffer.open_roots.swap_remove(index);
+                true
+            }
+            None => false,
+        };
+        if is_root && self.is_interesting(&span) {
+            buffer.keep = true;
+        }
+        // spans end child-first, so the slots of the open local roots are reserved for them
+        if is_root || buffer.spans.len() + buffer.open_roots.len() < self.config.max_spans_per_trace {
+            buffer.spans.push(span);
+        }
+
+        if is_root && buffer.open_roots.is_empty() {
+            let spans = self.decide(&mut state, trace_id);
+            drop(state);
+            self.export(spans);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        let mut state = self.state();
+        let trace_ids: Vec<TraceId> = state.traces.keys().copied().collect();
+        let mut spans = Vec::new();
+        for trace_id in trace_ids {
+            spans.append(&mut self.decide(&mut state, trace_id));
+        }
+        drop(state);
+        self.export(spans);
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{
+        sdk::trace::{self as sdktrace, TracerProvider},
+        trace::{SpanContext, TraceFlags, TraceState, Tracer, TracerProvider as _},
+        KeyValue,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    };
+
+    /// Records the names of the spans passed on by the tail sampling
+    #[derive(Debug, Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.0.lock().map(|names| names.clone()).unwrap_or_default()
+        }
+    }
+
+    impl SpanProcessor for Recorder {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            if let Ok(mut names) = self.0.lock() {
+                names.push(span.name.into_owned());
+            }
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    fn tail_sampled(config: TailSamplingConfig) -> (TracerProvider, Recorder) {
+        let recorder = Recorder::default();
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_sampler(Sampler::AlwaysOn))
+            .with_span_processor(TailSamplingProcessor::new(recorder.clone(), config))
+            .build();
+        (provider, recorder)
+    }
+
+    /// Keeps nothing by default, so the tests can turn on what they are about
+    fn config() -> TailSamplingConfig {
+        TailSamplingConfig {
+            keep_errors: false,
+            latency_threshold: None,
+            routes: Vec::new(),
+            base_ratio: 0.0,
+            max_traces: 16,
+            max_spans_per_trace: 16,
+        }
+    }
+
+    /// Starts a root span, and a child span of it which ends right away, returns the context of the root
+    fn trace(tracer: &impl Tracer<Span = Span>, name: &str) -> Context {
+        let cx = Context::current_with_span(tracer.start(format!("{} root", name)));
+        tracer.start_with_context(format!("{} child", name), &cx).end();
+        cx
+    }
+
+    #[test]
+    fn trace_with_an_error_is_kept() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            keep_errors: true,
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let cx = Context::current_with_span(tracer.start("failed root"));
+        let mut child = tracer.start_with_context("failed child", &cx);
+        child.set_status(StatusCode::Error, "boom".into());
+        child.end();
+        assert!(recorder.names().is_empty(), "buffered until the root ends");
+        cx.span().end();
+        trace(&tracer, "ok").span().end();
+
+        assert_eq!(recorder.names(), vec!["failed child", "failed root"]);
+    }
+
+    #[test]
+    fn slow_trace_is_kept() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            latency_threshold: Some(Duration::from_secs(1)),
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let started = SystemTime::now() - Duration::from_secs(2);
+        tracer
+            .span_builder("slow root")
+            .with_start_time(started)
+            .start(&tracer)
+            .end();
+        tracer.start("fast root").end();
+
+        assert_eq!(recorder.names(), vec!["slow root"]);
+    }
+
+    #[test]
+    fn trace_with_a_matching_route_is_kept() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            routes: vec!["/checkout".into(), "/admin/*".into()],
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        for (name, attribute) in [
+            ("checkout", trace::HTTP_ROUTE.string("/checkout")),
+            ("admin", trace::HTTP_TARGET.string("/admin/users?page=2")),
+            ("search", trace::HTTP_ROUTE.string("/search")),
+        ] {
+            let cx = trace(&tracer, name);
+            cx.span().set_attribute(attribute);
+            cx.span().end();
+        }
+
+        assert_eq!(
+            recorder.names(),
+            vec!["checkout child", "checkout root", "admin child", "admin root"]
+        );
+    }
+
+    #[test]
+    fn base_ratio_decides_the_other_traces() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            base_ratio: 1.0,
+            ..config()
+        });
+        trace(&provider.tracer("test"), "all").span().end();
+        assert_eq!(recorder.names(), vec!["all child", "all root"]);
+
+        let (provider, recorder) = tail_sampled(config());
+        trace(&provider.tracer("test"), "none").span().end();
+        assert!(recorder.names().is_empty());
+    }
+
+    #[test]
+    fn late_spans_follow_the_decision() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            routes: vec!["/kept".into()],
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        for route in ["/kept", "/dropped"] {
+            let cx = Context::current_with_span(tracer.start(format!("{} root", route)));
+            let late = tracer.start_with_context(format!("{} late", route), &cx);
+            cx.span().set_attribute(trace::HTTP_ROUTE.string(route));
+            cx.span().end();
+            drop(late);
+        }
+
+        assert_eq!(recorder.names(), vec!["/kept root", "/kept late"]);
+    }
+
+    #[test]
+    fn late_spans_of_forgotten_decisions_are_kept() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            max_traces: 1,
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let cx = Context::current_with_span(tracer.start("forgotten root"));
+        let late = tracer.start_with_context("forgotten late", &cx);
+        cx.span().end();
+        // only the decision about this trace is remembered from now on
+        tracer.start("other root").end();
+        drop(late);
+
+        assert_eq!(recorder.names(), vec!["forgotten late"]);
+    }
+
+    #[test]
+    fn oldest_trace_is_decided_early() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            keep_errors: true,
+            max_traces: 1,
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let oldest = Context::current_with_span(tracer.start("oldest root"));
+        let mut child = tracer.start_with_context("oldest child", &oldest);
+        child.set_status(StatusCode::Error, "boom".into());
+        child.end();
+        let newest = trace(&tracer, "newest");
+        assert_eq!(recorder.names(), vec!["oldest child"]);
+
+        // the early decision applies to the rest of the trace
+        oldest.span().end();
+        newest.span().end();
+        assert_eq!(recorder.names(), vec!["oldest child", "oldest root"]);
+    }
+
+    #[test]
+    fn spans_beyond_the_limit_are_dropped_but_the_root_is_kept() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            base_ratio: 1.0,
+            max_spans_per_trace: 2,
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let cx = Context::current_with_span(tracer.start("root"));
+        for name in ["first", "second", "third"] {
+            tracer.start_with_context(name, &cx).end();
+        }
+        cx.span().end();
+
+        assert_eq!(recorder.names(), vec!["first", "root"]);
+    }
+
+    #[test]
+    fn shutdown_decides_the_buffered_traces() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            keep_errors: true,
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+
+        let roots: Vec<Context> = [("failed", StatusCode::Error), ("ok", StatusCode::Ok)]
+            .iter()
+            .map(|(name, status)| {
+                let cx = Context::current_with_span(tracer.start(format!("{} root", name)));
+                let mut child = tracer.start_with_context(format!("{} child", name), &cx);
+                child.set_status(*status, String::new());
+                child.end();
+                cx
+            })
+            .collect();
+        assert!(recorder.names().is_empty());
+
+        // shuts the span processors down
+        drop(provider);
+        assert_eq!(recorder.names(), vec!["failed child"]);
+        drop(roots);
+    }
+
+    #[test]
+    fn spans_of_a_remote_parent_are_buffered_under_its_trace() {
+        let (provider, recorder) = tail_sampled(TailSamplingConfig {
+            routes: vec!["/remote".into()],
+            ..config()
+        });
+        let tracer = provider.tracer("test");
+        let remote = Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_bytes([1; 16]),
+            SpanId::from_bytes([1; 8]),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+
+        let server = tracer.start_with_context("server", &remote);
+        let cx = Context::current_with_span(server);
+        tracer.start_with_context("child", &cx).end();
+        cx.span().set_attribute(KeyValue::new(trace::HTTP_ROUTE, "/remote"));
+        cx.span().end();
+
+        assert_eq!(recorder.names(), vec!["child", "server"]);
+    }
+}
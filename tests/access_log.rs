@@ -0,0 +1,121 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{logs::Capture, logs::Record, request, Traces};
+use log::Level;
+use opentelemetry_tide::{AccessLogConfig, AccessLogMiddleware, OpenTelemetryTracingMiddleware, RequestIdMiddleware};
+use tide::http::Method;
+
+fn app(traces: &Traces, config: AccessLogConfig) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.with(RequestIdMiddleware::new());
+    app.with(AccessLogMiddleware::new_with_config(config));
+    app.at("/*").get(|req: tide::Request<()>| async move {
+        let status = match req.url().path() {
+            path if path.ends_with("/missing") => 404,
+            path if path.ends_with("/failing") => 500,
+            _ => 200,
+        };
+        Ok(tide::Response::builder(status).body("hello"))
+    });
+    app
+}
+
+/// Sends the request and returns the access log record for its path
+async fn access_log(app: &tide::Server<()>, path: &str) -> Record {
+    let capture = Capture::install();
+    let mut req = request(Method::Get, path);
+    req.insert_header("user-agent", "access-log-test");
+    req.insert_header("x-forwarded-for", "203.0.113.7");
+    let res: tide::http::Response = app.respond(req).await.expect("response");
+    assert!(res.header("x-request-id").is_some());
+
+    let records =
+        capture.records(|record| record.target.ends_with("access_log") && record.field("route") == Some(path));
+    assert_eq!(records.len(), 1, "{:#?}", records);
+    records.into_iter().next().expect("access log")
+}
+
+#[async_std::test]
+async fn record_has_all_fields_by_default() {
+    let traces = Traces::new();
+    let app = app(&traces, AccessLogConfig::default());
+
+    let record = access_log(&app, "/fields").await;
+    assert_eq!(record.message, "GET /fields 200");
+    assert_eq!(
+        record.keys(),
+        vec![
+            "bytes",
+            "client_ip",
+            "duration_ms",
+            "method",
+            "request_id",
+            "route",
+            "span_id",
+            "status",
+            "trace_id",
+            "user_agent"
+        ]
+    );
+    assert_eq!(record.field("method"), Some("GET"));
+    assert_eq!(record.field("status"), Some("200"));
+    assert_eq!(record.field("bytes"), Some("5"));
+    assert_eq!(record.field("client_ip"), Some("203.0.113.7"));
+    assert_eq!(record.field("user_agent"), Some("access-log-test"));
+
+    let span = traces.span("GET http://localhost/fields");
+    let trace_id = span.span_context.trace_id().to_string();
+    let span_id = span.span_context.span_id().to_string();
+    assert_eq!(record.field("trace_id"), Some(trace_id.as_str()));
+    assert_eq!(record.field("span_id"), Some(span_id.as_str()));
+}
+
+#[async_std::test]
+async fn disabled_fields_are_left_out() {
+    let traces = Traces::new();
+    let config = AccessLogConfig {
+        method: false,
+        duration: false,
+        client_ip: false,
+        user_agent: false,
+        trace_id: false,
+        span_id: false,
+        request_id: false,
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    let record = access_log(&app, "/disabled").await;
+    // the message does not depend on the fields
+    assert_eq!(record.message, "GET /disabled 200");
+    assert_eq!(record.keys(), vec!["bytes", "route", "status"]);
+}
+
+#[async_std::test]
+async fn level_follows_the_status() {
+    let traces = Traces::new();
+    let app = app(&traces, AccessLogConfig::default());
+
+    assert_eq!(access_log(&app, "/levels/ok").await.level, Level::Info);
+    assert_eq!(access_log(&app, "/levels/missing").await.level, Level::Warn);
+    assert_eq!(access_log(&app, "/levels/failing").await.level, Level::Error);
+}
+
+#[async_std::test]
+async fn levels_are_configurable() {
+    let traces = Traces::new();
+    let config = AccessLogConfig {
+        success_level: Level::Debug,
+        client_error_level: Level::Info,
+        server_error_level: Level::Warn,
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    assert_eq!(access_log(&app, "/custom/ok").await.level, Level::Debug);
+    assert_eq!(access_log(&app, "/custom/missing").await.level, Level::Info);
+    assert_eq!(access_log(&app, "/custom/failing").await.level, Level::Warn);
+}
@@ -0,0 +1,81 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, metric_value, metrics_middleware, request, scrape, Traces};
+use opentelemetry::sdk::propagation::{BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator};
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryTracingMiddleware, TracingConfig};
+use std::sync::Arc;
+use tide::http::Method;
+
+fn app(traces: &Traces) -> tide::Server<()> {
+    let propagator = TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+        Box::new(BaggagePropagator::new()),
+    ]);
+    let config = TracingConfig {
+        extract_propagator: Some(Arc::new(propagator)),
+        baggage_attributes: vec!["tenant.id".into()],
+        ..Default::default()
+    };
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(traces.tracer(), config));
+    app.with(metrics_middleware(MetricsConfig {
+        baggage_labels: vec!["tenant.id".into()],
+        max_baggage_label_values: 2,
+        ..Default::default()
+    }));
+    app.at("/*").get(|_| async { Ok("") });
+    app
+}
+
+async fn send(app: &tide::Server<()>, path: &str, baggage: &str) {
+    let mut req = request(Method::Get, path);
+    req.insert_header("baggage", baggage);
+    let _: tide::http::Response = app.respond(req).await.expect("response");
+}
+
+fn requests(metrics: &str, tenant: &str) -> Option<f64> {
+    metric_value(
+        metrics,
+        &["http_server_requests_count{", &format!("tenant_id=\"{}\"", tenant)],
+    )
+}
+
+#[async_std::test]
+async fn allowlisted_baggage_becomes_a_span_attribute() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    send(&app, "/attribute", "tenant.id=acme,client.tier=gold").await;
+
+    let span = traces.span("GET http://localhost/attribute");
+    assert_eq!(attribute(&span, "tenant.id"), Some("acme".into()));
+    assert_eq!(attribute(&span, "client.tier"), None);
+}
+
+#[async_std::test]
+async fn label_values_collapse_to_other_past_the_maximum() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    for tenant in ["acme", "beta", "gamma", "delta", "acme"] {
+        send(&app, "/labels", &format!("tenant.id={},client.tier=gold", tenant)).await;
+    }
+
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(requests(&metrics, "acme"), Some(2.0), "{}", metrics);
+    assert_eq!(requests(&metrics, "beta"), Some(1.0), "{}", metrics);
+    assert_eq!(requests(&metrics, "other"), Some(2.0), "{}", metrics);
+    assert!(!metrics.contains("gamma") && !metrics.contains("delta"), "{}", metrics);
+    assert!(!metrics.contains("client_tier"), "{}", metrics);
+
+    // the span attributes are not bounded
+    let tenants: Vec<_> = traces
+        .spans()
+        .iter()
+        .filter_map(|span| attribute(span, "tenant.id"))
+        .map(|value| value.as_str().into_owned())
+        .collect();
+    assert_eq!(tenants, vec!["acme", "beta", "gamma", "delta", "acme"]);
+}
@@ -0,0 +1,59 @@
+#![cfg(all(feature = "bootstrap", feature = "metrics"))]
+
+mod common;
+
+use async_std::task;
+use common::{get, metric_value, scrape, with_global_providers};
+use opentelemetry::global;
+use opentelemetry_tide::bootstrap::{self, TelemetryGuard};
+use std::env;
+
+// the environment is shared by the tests, so it is only touched with the global providers locked
+fn init(metrics_exporter: &str) -> TelemetryGuard {
+    env::set_var("OTEL_TRACES_EXPORTER", "none");
+    env::set_var("OTEL_SERVICE_NAME", "bootstrap-test");
+    env::set_var("OTEL_METRICS_EXPORTER", metrics_exporter);
+    bootstrap::init_from_env().expect("telemetry")
+}
+
+#[test]
+fn guard_serves_its_metrics_until_dropped() {
+    with_global_providers(|| {
+        let telemetry = init("prometheus");
+        let mut app = tide::new();
+        app.with(telemetry.metrics_middleware().expect("metrics middleware"));
+        app.at("/").get(|_| async { Ok("") });
+
+        let _ = task::block_on(get(&app, "/"));
+        let metrics = task::block_on(scrape(&app, "/metrics"));
+        assert_eq!(
+            metric_value(
+                &metrics,
+                &[
+                    "http_server_requests_count{",
+                    "http_route=\"/\"",
+                    "service_name=\"bootstrap-test\""
+                ]
+            ),
+            Some(1.0),
+            "{}",
+            metrics
+        );
+
+        drop(telemetry);
+        global::meter("after-shutdown")
+            .u64_counter("after_shutdown_count")
+            .init()
+            .add(1, &[]);
+        let metrics = task::block_on(scrape(&app, "/metrics"));
+        assert!(!metrics.contains("after_shutdown_count"), "{}", metrics);
+    });
+}
+
+#[test]
+fn no_metrics_middleware_without_exporter() {
+    with_global_providers(|| {
+        let telemetry = init("none");
+        assert!(telemetry.metrics_middleware().is_none());
+    });
+}
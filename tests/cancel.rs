@@ -0,0 +1,81 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, event_names, metric_value, metrics_middleware, request, scrape, Traces};
+use futures_util::FutureExt;
+use opentelemetry::trace::StatusCode;
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryTracingMiddleware};
+use std::time::Duration;
+use tide::http::{Method, Response};
+
+fn slow_app() -> tide::Server<()> {
+    let mut app = tide::new();
+    app.at("/slow").get(|_| async {
+        async_std::task::sleep(Duration::from_secs(10)).await;
+        Ok("")
+    });
+    app
+}
+
+/// Starts the request and drops it while the handler is still pending, like tide does on client disconnects
+fn cancel(app: &tide::Server<()>, path: &str) {
+    let pending = app.respond::<_, Response>(request(Method::Get, path)).now_or_never();
+    assert!(pending.is_none());
+}
+
+#[async_std::test]
+async fn cancelled_request_ends_the_span_with_error() {
+    let traces = Traces::new();
+    let mut app = slow_app();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+
+    cancel(&app, "/slow");
+    let span = traces.span("GET http://localhost/slow");
+    assert_eq!(span.status_code, StatusCode::Error);
+    assert_eq!(attribute(&span, "http.cancelled"), Some(true.into()));
+    assert!(event_names(&span).contains(&"request.cancelled".to_owned()));
+}
+
+#[async_std::test]
+async fn cancelled_request_is_counted_with_status_499() {
+    let mut app = slow_app();
+    app.with(metrics_middleware(MetricsConfig::default()));
+
+    cancel(&app, "/slow");
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(&metrics, &["http_server_requests_cancelled{", "http_route=\"/slow\""]),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+    assert_eq!(
+        metric_value(
+            &metrics,
+            &[
+                "http_server_request_duration_seconds_count{",
+                "http_status_code=\"499\""
+            ]
+        ),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+}
+
+#[async_std::test]
+async fn completed_request_is_not_cancelled() {
+    let mut app = tide::new();
+    app.with(metrics_middleware(MetricsConfig::default()));
+    app.at("/").get(|_| async { Ok("") });
+
+    let _ = common::get(&app, "/").await;
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(&metrics, &["http_server_requests_cancelled{"]),
+        None,
+        "{}",
+        metrics
+    );
+}
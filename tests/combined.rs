@@ -0,0 +1,123 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, event_names, get, metric_value, request, scrape, Traces};
+use futures_util::FutureExt;
+use opentelemetry::trace::StatusCode;
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryMiddleware, TracingConfig};
+use std::time::Duration;
+use tide::http::{Method, Response};
+
+fn app(traces: &Traces) -> tide::Server<()> {
+    let tracer = traces.tracer();
+    let mut app = tide::new();
+    app.with(common::with_global_providers(|| {
+        OpenTelemetryMiddleware::new_with_config(
+            tracer,
+            TracingConfig::default(),
+            MetricsConfig {
+                catch_panics: true,
+                ..Default::default()
+            },
+        )
+    }));
+    app.at("/slow").get(|_| async {
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        Ok("")
+    });
+    app.at("/pending").get(|_| async {
+        async_std::task::sleep(Duration::from_secs(10)).await;
+        Ok("")
+    });
+    app.at("/panic").get(|_| async {
+        if true {
+            panic!("boom");
+        }
+        Ok("")
+    });
+    app
+}
+
+fn duration_metric(metrics: &str, metric: &str, status: u16) -> Option<f64> {
+    let status = format!("http_status_code=\"{}\"", status);
+    metric_value(metrics, &[metric, &status])
+}
+
+#[async_std::test]
+async fn span_duration_is_the_recorded_duration() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let _ = get(&app, "/slow").await;
+    let span = traces.span("GET http://localhost/slow");
+    let span_duration = span
+        .end_time
+        .duration_since(span.start_time)
+        .expect("span ends after it starts");
+    assert!(span_duration >= Duration::from_millis(10));
+
+    let metrics = scrape(&app, "/metrics").await;
+    let recorded = duration_metric(&metrics, "http_server_request_duration_seconds_sum{", 200);
+    assert_eq!(recorded, Some(span_duration.as_secs_f64()), "{}", metrics);
+}
+
+#[async_std::test]
+async fn panic_is_recorded_once_on_the_span_and_the_metrics() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let res = get(&app, "/panic").await;
+    assert_eq!(res.status(), 500);
+
+    let span = traces.span("GET http://localhost/panic");
+    assert_eq!(span.status_code, StatusCode::Error);
+    assert_eq!(span.status_message, "boom");
+    assert!(event_names(&span).contains(&"exception".to_owned()));
+    assert_eq!(attribute(&span, "http.status_code"), Some(500.into()));
+
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        duration_metric(&metrics, "http_server_request_duration_seconds_count{", 500),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+}
+
+#[async_std::test]
+async fn client_disconnect_ends_the_span_and_counts_the_request_as_cancelled() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    // dropped while the handler is still pending, like tide does on client disconnects
+    let pending = app
+        .respond::<_, Response>(request(Method::Get, "/pending"))
+        .now_or_never();
+    assert!(pending.is_none());
+
+    let span = traces.span("GET http://localhost/pending");
+    assert_eq!(span.status_code, StatusCode::Error);
+    assert_eq!(attribute(&span, "http.cancelled"), Some(true.into()));
+    let span_duration = span
+        .end_time
+        .duration_since(span.start_time)
+        .expect("span ends after it starts");
+
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(
+            &metrics,
+            &["http_server_requests_cancelled{", "http_route=\"/pending\""]
+        ),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+    assert_eq!(
+        duration_metric(&metrics, "http_server_request_duration_seconds_sum{", 499),
+        Some(span_duration.as_secs_f64()),
+        "{}",
+        metrics
+    );
+}
@@ -0,0 +1,92 @@
+//! A logger keeping the log records, to assert on them
+
+use log::{
+    kv::{Error, Key, Value, VisitSource},
+    Level, LevelFilter, Log, Metadata,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, Once},
+};
+
+/// A captured log record, with its key-values formatted
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Record {
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        self.fields.keys().map(String::as_str).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Capture {
+    records: Mutex<Vec<Record>>,
+}
+
+static CAPTURE: Capture = Capture {
+    records: Mutex::new(Vec::new()),
+};
+
+impl Capture {
+    /// The capturing logger, to be wrapped by another logger
+    pub fn logger() -> &'static Self {
+        &CAPTURE
+    }
+
+    /// Installs the capturing logger as the global logger, once per test binary
+    pub fn install() -> &'static Self {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CAPTURE).expect("logger");
+            log::set_max_level(LevelFilter::Trace);
+        });
+        &CAPTURE
+    }
+
+    /// The records captured so far, which match the filter
+    pub fn records(&self, filter: impl Fn(&Record) -> bool) -> Vec<Record> {
+        super::lock(&self.records)
+            .iter()
+            .filter(|record| filter(record))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        struct Fields(BTreeMap<String, String>);
+
+        impl<'kvs> VisitSource<'kvs> for Fields {
+            fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+                let _ = self.0.insert(key.to_string(), value.to_string());
+                Ok(())
+            }
+        }
+
+        let mut fields = Fields(BTreeMap::new());
+        let _ = record.key_values().visit(&mut fields);
+        super::lock(&self.records).push(Record {
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+            fields: fields.0,
+        });
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,157 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+pub mod logs;
+
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    sdk::{
+        export::trace::SpanData,
+        trace::{config, Sampler, ShouldSample, Span, SpanProcessor, TracerProvider},
+    },
+    trace::{TraceResult, TracerProvider as _},
+    Context, Key, Value,
+};
+#[cfg(feature = "metrics")]
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryMetricsMiddleware};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tide::http::{Method, Request, Response};
+
+// the middlewares pick up the global tracer and meter providers on construction,
+// so tests running in parallel must not install them at the same time
+static GLOBAL_PROVIDERS: Mutex<()> = Mutex::new(());
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// A tracer provider recording the spans which ended
+#[derive(Debug)]
+pub struct Traces {
+    provider: TracerProvider,
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+#[derive(Debug)]
+struct Recorder(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Recorder {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        lock(&self.0).push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+impl Default for Traces {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Traces {
+    /// Records every span
+    pub fn new() -> Self {
+        Self::with_sampler(Sampler::AlwaysOn)
+    }
+
+    pub fn with_sampler(sampler: impl ShouldSample + 'static) -> Self {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let provider = TracerProvider::builder()
+            .with_config(config().with_sampler(sampler))
+            .with_span_processor(Recorder(spans.clone()))
+            .build();
+        Self { provider, spans }
+    }
+
+    /// A tracer of this provider, as taken by the middlewares
+    pub fn tracer(&self) -> BoxedTracer {
+        self.install(|| global::tracer("test"))
+    }
+
+    /// A tracer of the provider itself, for integrations which need the concrete type
+    pub fn sdk_tracer(&self) -> opentelemetry::sdk::trace::Tracer {
+        self.provider.tracer("test")
+    }
+
+    /// Runs a constructor which takes its tracer from the global provider, with this provider installed
+    pub fn install<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _lock = lock(&GLOBAL_PROVIDERS);
+        let _ = global::set_tracer_provider(self.provider.clone());
+        f()
+    }
+
+    pub fn spans(&self) -> Vec<SpanData> {
+        lock(&self.spans).clone()
+    }
+
+    /// The only span with the given name
+    pub fn span(&self, name: &str) -> SpanData {
+        let mut spans: Vec<SpanData> = self.spans().into_iter().filter(|span| span.name == name).collect();
+        assert_eq!(
+            spans.len(),
+            1,
+            "expected one span named {:?} in {:#?}",
+            name,
+            self.spans()
+        );
+        spans.remove(0)
+    }
+}
+
+/// Looks up a span attribute
+pub fn attribute(span: &SpanData, key: &'static str) -> Option<Value> {
+    span.attributes.get(&Key::from_static_str(key)).cloned()
+}
+
+/// The names of the span events
+pub fn event_names(span: &SpanData) -> Vec<String> {
+    span.events.iter().map(|event| event.name.to_string()).collect()
+}
+
+/// Creates a metrics middleware with its own exporter
+#[cfg(feature = "metrics")]
+pub fn metrics_middleware(config: MetricsConfig) -> OpenTelemetryMetricsMiddleware {
+    let _lock = lock(&GLOBAL_PROVIDERS);
+    OpenTelemetryMetricsMiddleware::new(config)
+}
+
+/// Runs the constructor of a middleware which installs global providers
+pub fn with_global_providers<T>(f: impl FnOnce() -> T) -> T {
+    let _lock = lock(&GLOBAL_PROVIDERS);
+    f()
+}
+
+pub fn request(method: Method, path: &str) -> Request {
+    Request::new(method, format!("http://localhost{}", path).as_str())
+}
+
+pub async fn get<State: Clone + Send + Sync + 'static>(app: &tide::Server<State>, path: &str) -> Response {
+    app.respond(request(Method::Get, path)).await.expect("response")
+}
+
+/// Scrapes the metrics of the app
+pub async fn scrape<State: Clone + Send + Sync + 'static>(app: &tide::Server<State>, route: &str) -> String {
+    get(app, route).await.body_string().await.expect("metrics")
+}
+
+/// The value of a metric line (the line has to contain all the given parts), as scraped from the app
+pub fn metric_value(metrics: &str, parts: &[&str]) -> Option<f64> {
+    metrics
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find(|line| parts.iter().all(|part| line.contains(part)))
+        .and_then(|line| line.rsplit(' ').next())
+        .and_then(|value| value.parse().ok())
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{attribute, get, Traces};
+use opentelemetry::trace::{SpanKind, StatusCode};
+use opentelemetry_tide::{traced, OpenTelemetryTracingMiddleware, TracedEndpoint, TracedRouteExt};
+
+async fn hello(_req: tide::Request<()>) -> tide::Result {
+    Ok("Hello!".into())
+}
+
+async fn fail(_req: tide::Request<()>) -> tide::Result {
+    Err(tide::Error::from_str(503, "upstream down"))
+}
+
+fn app(traces: &Traces) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    traces.install(|| {
+        app.at("/hello").get(traced(hello));
+        app.at("/fail").get(traced(fail));
+        app.at("/named").get(TracedEndpoint::named("greeting", hello));
+        app.at("/users/:id").get_traced(|_| async { Ok("user") });
+    });
+    app
+}
+
+#[async_std::test]
+async fn handler_span_is_a_child_of_the_server_span() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let res = get(&app, "/hello").await;
+    assert_eq!(res.status(), 200);
+
+    let server = traces.span("GET http://localhost/hello");
+    let handler = traces.span("hello");
+    assert_eq!(handler.span_kind, SpanKind::Internal);
+    assert_eq!(handler.parent_span_id, server.span_context.span_id());
+    assert_eq!(handler.span_context.trace_id(), server.span_context.trace_id());
+    assert_eq!(attribute(&handler, "code.function"), Some("hello".into()));
+    assert!(handler.end_time <= server.end_time);
+}
+
+#[async_std::test]
+async fn handler_span_can_be_named() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let _ = get(&app, "/named").await;
+    let _ = traces.span("greeting");
+}
+
+#[async_std::test]
+async fn route_extension_names_the_span_after_the_route() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let _ = get(&app, "/users/42").await;
+    let handler = traces.span("GET /users/:id");
+    assert_eq!(attribute(&handler, "http.route"), Some("/users/:id".into()));
+    assert_eq!(attribute(&handler, "http.status_code"), Some(200.into()));
+}
+
+#[async_std::test]
+async fn handler_errors_are_recorded() {
+    let traces = Traces::new();
+    let app = app(&traces);
+
+    let res = get(&app, "/fail").await;
+    assert_eq!(res.status(), 503);
+
+    let handler = traces.span("fail");
+    assert_eq!(handler.status_code, StatusCode::Error);
+    assert_eq!(handler.status_message, "upstream down");
+    assert_eq!(attribute(&handler, "http.status_code"), Some(503.into()));
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{get, Traces};
+use opentelemetry_tide::{
+    ErrorFormat, ErrorResponseConfig, ErrorResponseMiddleware, OpenTelemetryTracingMiddleware, RequestIdMiddleware,
+};
+use serde_json::{json, Value};
+
+fn app(traces: &Traces, config: ErrorResponseConfig) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.with(RequestIdMiddleware::new());
+    app.with(ErrorResponseMiddleware::new_with_config(config));
+    app.at("/teapot")
+        .get(|_| async { Err::<String, _>(tide::Error::from_str(418, "I'm a teapot")) });
+    app.at("/failing")
+        .get(|_| async { Err::<String, _>(tide::Error::from_str(500, "db password wrong")) });
+    app.at("/fine").get(|_| async { Ok("fine") });
+    app
+}
+
+/// Returns the content type and the parsed body of the response, with the request id checked and removed
+async fn error_response(app: &tide::Server<()>, path: &str) -> (String, Value) {
+    let mut res = get(app, path).await;
+    let content_type = res
+        .content_type()
+        .map(|mime| mime.essence().to_owned())
+        .unwrap_or_default();
+    let request_id = res.header("x-request-id").map(|values| values.last().to_string());
+    let mut body: Value = serde_json::from_str(&res.body_string().await.expect("body")).expect("json");
+    let body_request_id = body.as_object_mut().and_then(|body| body.remove("request_id"));
+    assert_eq!(
+        body_request_id.and_then(|id| id.as_str().map(ToOwned::to_owned)),
+        request_id
+    );
+    (content_type, body)
+}
+
+#[async_std::test]
+async fn problem_json_contains_the_trace_id() {
+    let traces = Traces::new();
+    let config = ErrorResponseConfig {
+        trace_url_template: Some("https://jaeger.example.com/trace/{trace_id}".into()),
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    let (content_type, body) = error_response(&app, "/teapot").await;
+    let trace_id = traces
+        .span("GET http://localhost/teapot")
+        .span_context
+        .trace_id()
+        .to_string();
+    assert_eq!(content_type, "application/problem+json");
+    assert_eq!(
+        body,
+        json!({
+            "type": "about:blank",
+            "title": "I'm a teapot",
+            "status": 418,
+            "detail": "I'm a teapot",
+            "trace_id": trace_id,
+            "trace_url": format!("https://jaeger.example.com/trace/{}", trace_id),
+        })
+    );
+}
+
+#[async_std::test]
+async fn server_errors_hide_their_message() {
+    let traces = Traces::new();
+    let app = app(&traces, ErrorResponseConfig::default());
+
+    let (_, body) = error_response(&app, "/failing").await;
+    let trace_id = traces
+        .span("GET http://localhost/failing")
+        .span_context
+        .trace_id()
+        .to_string();
+    assert_eq!(
+        body,
+        json!({
+            "type": "about:blank",
+            "title": "Internal Server Error",
+            "status": 500,
+            "trace_id": trace_id,
+        })
+    );
+}
+
+#[async_std::test]
+async fn json_format() {
+    let traces = Traces::new();
+    let config = ErrorResponseConfig {
+        format: ErrorFormat::Json,
+        expose_server_errors: true,
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    let (content_type, body) = error_response(&app, "/failing").await;
+    let trace_id = traces
+        .span("GET http://localhost/failing")
+        .span_context
+        .trace_id()
+        .to_string();
+    assert_eq!(content_type, "application/json");
+    assert_eq!(
+        body,
+        json!({
+            "status": 500,
+            "error": "Internal Server Error",
+            "message": "db password wrong",
+            "trace_id": trace_id,
+        })
+    );
+}
+
+#[async_std::test]
+async fn successful_responses_are_untouched() {
+    let traces = Traces::new();
+    let app = app(&traces, ErrorResponseConfig::default());
+
+    let mut res = get(&app, "/fine").await;
+    assert_eq!(res.body_string().await.expect("body"), "fine");
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use common::{attribute, Traces};
+use opentelemetry::{
+    global,
+    sdk::export::trace::SpanData,
+    trace::{FutureExt, SpanKind, StatusCode, TraceContextExt, Tracer},
+    Context,
+};
+use std::{fmt::Debug, future::Future, sync::OnceLock};
+
+// the macro takes the global tracer on every call, so all tests share one provider
+fn traces() -> &'static Traces {
+    static TRACES: OnceLock<Traces> = OnceLock::new();
+    TRACES.get_or_init(|| {
+        let traces = Traces::new();
+        traces.install(|| ());
+        traces
+    })
+}
+
+/// Runs the future within a parent span and returns the spans of its trace, besides the parent
+async fn in_parent<F: Future>(future: F) -> (F::Output, SpanData, Vec<SpanData>) {
+    let tracer = global::tracer("test");
+    let cx = Context::current_with_span(tracer.start("parent"));
+    let output = future.with_context(cx.clone()).await;
+    cx.span().end();
+
+    let span_context = cx.span().span_context().clone();
+    let (parent, children): (Vec<SpanData>, Vec<SpanData>) = traces()
+        .spans()
+        .into_iter()
+        .filter(|span| span.span_context.trace_id() == span_context.trace_id())
+        .partition(|span| span.span_context.span_id() == span_context.span_id());
+    (output, parent.into_iter().next().expect("parent span"), children)
+}
+
+fn only(spans: Vec<SpanData>) -> SpanData {
+    assert_eq!(spans.len(), 1, "{:#?}", spans);
+    spans.into_iter().next().expect("span")
+}
+
+#[opentelemetry_tide::instrument]
+async fn plain_async(value: u8) -> u8 {
+    value + 1
+}
+
+#[opentelemetry_tide::instrument(name = "user.load", args(id, name))]
+async fn load_user(id: u64, name: &str, _secret: &str) -> String {
+    format!("{} {}", id, name)
+}
+
+#[opentelemetry_tide::instrument(args(input))]
+fn parse(input: &str) -> Result<u32, std::num::ParseIntError> {
+    let value: u32 = input.parse()?;
+    Ok(value * 2)
+}
+
+#[opentelemetry_tide::instrument(args(items))]
+fn count<T: Debug>(items: &[T]) -> usize {
+    items.len()
+}
+
+struct Repository;
+
+impl Repository {
+    #[opentelemetry_tide::instrument(args(id))]
+    async fn find(&self, id: u32) -> Option<u32> {
+        Some(id)
+    }
+}
+
+#[opentelemetry_tide::instrument]
+async fn handler(req: tide::Request<()>) -> tide::Result {
+    let id: u32 = req.param("id")?.parse()?;
+    Ok(format!("user {}", id).into())
+}
+
+#[async_std::test]
+async fn async_function_gets_a_child_span() {
+    let _ = traces();
+    let (output, parent, children) = in_parent(plain_async(1)).await;
+    assert_eq!(output, 2);
+
+    let span = only(children);
+    assert_eq!(span.name, "plain_async");
+    assert_eq!(span.span_kind, SpanKind::Internal);
+    assert_eq!(span.parent_span_id, parent.span_context.span_id());
+    assert_eq!(span.status_code, StatusCode::Unset);
+    assert_eq!(attribute(&span, "code.function"), Some("plain_async".into()));
+    assert_eq!(attribute(&span, "code.namespace"), Some("instrument".into()));
+}
+
+#[async_std::test]
+async fn selected_arguments_become_attributes() {
+    let _ = traces();
+    let (output, _, children) = in_parent(load_user(42, "bob", "hunter2")).await;
+    assert_eq!(output, "42 bob");
+
+    let span = only(children);
+    assert_eq!(span.name, "user.load");
+    assert_eq!(attribute(&span, "id"), Some("42".into()));
+    assert_eq!(attribute(&span, "name"), Some("\"bob\"".into()));
+    assert_eq!(attribute(&span, "_secret"), None);
+    assert_eq!(attribute(&span, "code.function"), Some("load_user".into()));
+}
+
+#[async_std::test]
+async fn sync_result_functions_record_errors() {
+    let _ = traces();
+    let (output, _, children) = in_parent(async { (parse("21"), parse("twenty")) }).await;
+    assert_eq!(output.0, Ok(42));
+    assert!(output.1.is_err());
+
+    let (ok, failed): (Vec<SpanData>, Vec<SpanData>) = children
+        .into_iter()
+        .partition(|span| attribute(span, "input") == Some("\"21\"".into()));
+    let ok = only(ok);
+    assert_eq!(ok.name, "parse");
+    assert_eq!(ok.status_code, StatusCode::Unset);
+    let failed = only(failed);
+    assert_eq!(failed.status_code, StatusCode::Error);
+    assert_eq!(failed.status_message, "invalid digit found in string");
+}
+
+#[async_std::test]
+async fn generic_functions_are_instrumented() {
+    let _ = traces();
+    let (output, _, children) = in_parent(async { count(&["a", "b"]) }).await;
+    assert_eq!(output, 2);
+
+    let span = only(children);
+    assert_eq!(span.name, "count");
+    assert_eq!(attribute(&span, "items"), Some("[\"a\", \"b\"]".into()));
+}
+
+#[async_std::test]
+async fn methods_are_instrumented() {
+    let _ = traces();
+    let (output, _, children) = in_parent(Repository.find(7)).await;
+    assert_eq!(output, Some(7));
+
+    let span = only(children);
+    assert_eq!(span.name, "find");
+    assert_eq!(attribute(&span, "id"), Some("7".into()));
+}
+
+#[async_std::test]
+async fn handler_errors_mark_the_span() {
+    let _ = traces();
+    let mut app = tide::new();
+    app.at("/users/:id").get(handler);
+
+    let (statuses, _, children) = in_parent(async {
+        let ok = common::get(&app, "/users/1").await.status();
+        let failed = common::get(&app, "/users/abc").await.status();
+        (ok, failed)
+    })
+    .await;
+    assert_eq!(statuses, (tide::StatusCode::Ok, tide::StatusCode::InternalServerError));
+
+    assert_eq!(children.len(), 2, "{:#?}", children);
+    assert!(children.iter().all(|span| span.name == "handler"));
+    let errors: Vec<&SpanData> = children
+        .iter()
+        .filter(|span| span.status_code == StatusCode::Error)
+        .collect();
+    assert_eq!(errors.len(), 1, "{:#?}", children);
+    assert_eq!(errors[0].status_message, "invalid digit found in string");
+}
+
+#[test]
+fn unsupported_arguments_fail_to_compile() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/instrument/*.rs");
+}
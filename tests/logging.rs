@@ -0,0 +1,185 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{
+    event_names,
+    logs::{Capture, Record},
+    Traces,
+};
+use log::{kv::Value, Level, Log};
+use opentelemetry::{
+    sdk::trace::Sampler,
+    trace::{TraceContextExt, Tracer},
+    KeyValue,
+};
+use opentelemetry_tide::logging::{SpanEventLogger, TraceContextLogger};
+use std::sync::Once;
+
+/// Logs a record with the given target (to tell the records of the tests apart) and key-values
+fn log_with(logger: &dyn Log, target: &str, key_values: &[(&str, &str)]) {
+    logger.log(
+        &log::Record::builder()
+            .args(format_args!("message"))
+            .level(Level::Info)
+            .target(target)
+            .key_values(&key_values)
+            .build(),
+    );
+}
+
+fn records(target: &str) -> Vec<Record> {
+    Capture::logger().records(|record| record.target == target)
+}
+
+#[test]
+fn records_within_a_span_get_its_context() {
+    let traces = Traces::new();
+    let logger = TraceContextLogger::new(Capture::logger());
+
+    let span_context = traces.tracer().in_span("span", |cx| {
+        log_with(&logger, "within_span", &[("user", "alice")]);
+        cx.span().span_context().clone()
+    });
+
+    let records = records("within_span");
+    assert_eq!(records.len(), 1, "{:#?}", records);
+    let record = &records[0];
+    assert_eq!(record.keys(), vec!["span_id", "trace_flags", "trace_id", "user"]);
+    assert_eq!(record.field("user"), Some("alice"));
+    assert_eq!(
+        record.field("trace_id"),
+        Some(span_context.trace_id().to_string().as_str())
+    );
+    assert_eq!(
+        record.field("span_id"),
+        Some(span_context.span_id().to_string().as_str())
+    );
+    assert_eq!(record.field("trace_flags"), Some("01"));
+}
+
+#[test]
+fn records_outside_of_a_span_are_untouched() {
+    let logger = TraceContextLogger::new(Capture::logger());
+
+    log_with(&logger, "outside_span", &[("user", "bob")]);
+
+    let records = records("outside_span");
+    assert_eq!(records.len(), 1, "{:#?}", records);
+    assert_eq!(records[0].keys(), vec!["user"]);
+}
+
+#[test]
+fn records_with_a_trace_id_are_untouched() {
+    let traces = Traces::new();
+    let logger = TraceContextLogger::new(Capture::logger());
+
+    traces.tracer().in_span("span", |_| {
+        log_with(&logger, "own_trace_id", &[("trace_id", "upstream")]);
+    });
+
+    let records = records("own_trace_id");
+    assert_eq!(records.len(), 1, "{:#?}", records);
+    assert_eq!(records[0].keys(), vec!["trace_id"]);
+    assert_eq!(records[0].field("trace_id"), Some("upstream"));
+}
+
+#[async_std::test]
+async fn handler_logs_join_the_server_span() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        TraceContextLogger::new(Capture::logger())
+            .install(log::LevelFilter::Trace)
+            .expect("logger");
+    });
+    let traces = Traces::new();
+    let mut app = tide::new();
+    app.with(opentelemetry_tide::OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.at("/orders").get(|_| async {
+        kv_log_macro::info!("loading orders", { count: 3 });
+        Ok("orders")
+    });
+
+    let _ = common::get(&app, "/orders").await;
+
+    let server = traces.span("GET http://localhost/orders");
+    let records = records(module_path!());
+    assert_eq!(records.len(), 1, "{:#?}", records);
+    assert_eq!(records[0].message, "loading orders");
+    assert_eq!(records[0].field("count"), Some("3"));
+    assert_eq!(
+        records[0].field("trace_id"),
+        Some(server.span_context.trace_id().to_string().as_str())
+    );
+    assert_eq!(
+        records[0].field("span_id"),
+        Some(server.span_context.span_id().to_string().as_str())
+    );
+}
+
+#[test]
+fn records_become_events_of_the_current_span() {
+    let traces = Traces::new();
+    let logger = SpanEventLogger::new(Capture::logger());
+
+    traces.tracer().in_span("with_events", |_| {
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("payment declined"))
+                .level(Level::Warn)
+                .target("span_events")
+                .key_values(&[
+                    ("retry", Value::from(true)),
+                    ("attempt", Value::from(2i64)),
+                    ("amount", Value::from(9.5f64)),
+                    ("card", Value::from("visa")),
+                ])
+                .build(),
+        );
+    });
+
+    let span = traces.span("with_events");
+    assert_eq!(event_names(&span), vec!["payment declined"]);
+    let event = span.events.iter().next().expect("event");
+    assert_eq!(
+        event.attributes,
+        vec![
+            KeyValue::new("level", "WARN"),
+            KeyValue::new("target", "span_events"),
+            KeyValue::new("retry", true),
+            KeyValue::new("attempt", 2i64),
+            KeyValue::new("amount", 9.5f64),
+            KeyValue::new("card", "visa"),
+        ]
+    );
+    // still logged as usual
+    assert_eq!(records("span_events").len(), 1);
+}
+
+#[test]
+fn records_below_the_minimum_level_are_only_logged() {
+    let traces = Traces::new();
+    let logger = SpanEventLogger::new(Capture::logger()).with_min_level(Level::Warn);
+
+    traces.tracer().in_span("below_min_level", |_| {
+        log_with(&logger, "below_min_level", &[]);
+    });
+
+    assert!(event_names(&traces.span("below_min_level")).is_empty());
+    assert_eq!(records("below_min_level").len(), 1);
+}
+
+#[test]
+fn records_outside_of_a_recording_span_are_only_logged() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let logger = SpanEventLogger::new(Capture::logger());
+
+    log_with(&logger, "no_span", &[]);
+    traces.tracer().in_span("unsampled", |cx| {
+        assert!(!cx.span().is_recording());
+        log_with(&logger, "no_span", &[]);
+    });
+
+    assert!(traces.spans().is_empty());
+    assert_eq!(records("no_span").len(), 2);
+}
@@ -0,0 +1,94 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, get, metric_value, metrics_middleware, scrape, Traces};
+use opentelemetry::trace::StatusCode;
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryTracingMiddleware, TracingConfig};
+
+fn panicking_app(traces: &Traces) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(
+        traces.tracer(),
+        TracingConfig {
+            catch_panics: true,
+            ..Default::default()
+        },
+    ));
+    app.with(metrics_middleware(MetricsConfig {
+        catch_panics: true,
+        ..Default::default()
+    }));
+    app.at("/").get(|_| async {
+        if true {
+            panic!("boom");
+        }
+        Ok("")
+    });
+    app
+}
+
+#[async_std::test]
+async fn panic_becomes_internal_server_error() {
+    let traces = Traces::new();
+    let app = panicking_app(&traces);
+
+    let res = get(&app, "/").await;
+    assert_eq!(res.status(), 500);
+}
+
+#[async_std::test]
+async fn panic_is_recorded_on_the_server_span() {
+    let traces = Traces::new();
+    let app = panicking_app(&traces);
+
+    let _ = get(&app, "/").await;
+    let span = traces.span("GET http://localhost/");
+    assert_eq!(span.status_code, StatusCode::Error);
+    assert_eq!(span.status_message, "boom");
+    let exception = span
+        .events
+        .iter()
+        .find(|event| event.name == "exception")
+        .expect("exception event");
+    let message = exception
+        .attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == "exception.message")
+        .map(|attribute| attribute.value.as_str().into_owned());
+    assert_eq!(message.as_deref(), Some("boom"));
+    assert_eq!(attribute(&span, "http.status_code"), Some(500.into()));
+}
+
+#[async_std::test]
+async fn panic_is_counted_as_server_error() {
+    let traces = Traces::new();
+    let app = panicking_app(&traces);
+
+    let _ = get(&app, "/").await;
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(&metrics, &["http_server_errors_count{", "http_status_code=\"500\""]),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+}
+
+#[async_std::test]
+async fn panics_propagate_without_the_guard() {
+    let traces = Traces::new();
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.at("/").get(|_| async {
+        if true {
+            panic!("boom");
+        }
+        Ok("")
+    });
+
+    let res = futures_util::FutureExt::catch_unwind(std::panic::AssertUnwindSafe(get(&app, "/"))).await;
+    assert!(res.is_err());
+    let span = traces.span("GET http://localhost/");
+    assert_eq!(span.status_code, StatusCode::Error);
+}
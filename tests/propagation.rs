@@ -0,0 +1,362 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{request, Traces};
+use opentelemetry::{
+    baggage::BaggageExt,
+    propagation::{Extractor, Injector, TextMapPropagator},
+    sdk::propagation::{BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator},
+    trace::{SpanId, TraceContextExt},
+    Context,
+};
+use opentelemetry_tide::{
+    propagation::{HeaderExtractor, HeaderInjector},
+    OpenTelemetryTracingMiddleware, TracingConfig,
+};
+use std::sync::Arc;
+use tide::http::Method;
+
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+#[test]
+fn single_values_are_returned_as_they_are() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    let extractor = HeaderExtractor::new(&req);
+
+    assert_eq!(extractor.get("traceparent"), Some(TRACEPARENT));
+    assert_eq!(extractor.get("TraceParent"), Some(TRACEPARENT));
+    assert_eq!(extractor.get("tracestate"), None);
+    assert_eq!(extractor.keys(), vec!["traceparent"]);
+}
+
+#[test]
+fn multiple_values_are_joined_with_commas() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    req.append_header("tracestate", "congo=t61rcWkgMzE");
+    req.append_header("tracestate", "rojo=00f067aa0ba902b7");
+    let extractor = HeaderExtractor::new(&req);
+
+    assert_eq!(
+        extractor.get("Tracestate"),
+        Some("congo=t61rcWkgMzE,rojo=00f067aa0ba902b7")
+    );
+    let mut keys = extractor.keys();
+    keys.sort_unstable();
+    assert_eq!(keys, vec!["traceparent", "tracestate"]);
+
+    let cx = TraceContextPropagator::new().extract(&extractor);
+    let span = cx.span();
+    let trace_state = span.span_context().trace_state();
+    assert_eq!(trace_state.get("congo"), Some("t61rcWkgMzE"));
+    assert_eq!(trace_state.get("rojo"), Some("00f067aa0ba902b7"));
+}
+
+#[test]
+fn injected_headers_replace_previous_values() {
+    let mut res = tide::Response::new(200);
+    res.append_header("x-trace", "stale");
+    res.append_header("x-trace", "older");
+    let mut injector = HeaderInjector::new(&mut res);
+    injector.set("X-Trace", "fresh".to_owned());
+    injector.set("x-invalid", "non-ascii ü".to_owned());
+
+    let values = res.header("x-trace").expect("header");
+    assert_eq!(
+        values.iter().map(|value| value.as_str()).collect::<Vec<_>>(),
+        vec!["fresh"]
+    );
+    assert!(res.header("x-invalid").is_none());
+}
+
+#[async_std::test]
+async fn baggage_of_multiple_headers_reaches_the_handler() {
+    let traces = Traces::new();
+    let propagator = TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+        Box::new(BaggagePropagator::new()),
+    ]);
+    let config = TracingConfig {
+        extract_propagator: Some(Arc::new(propagator)),
+        ..Default::default()
+    };
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(traces.tracer(), config));
+    app.at("/").get(|_| async {
+        let cx = Context::current();
+        let baggage = cx.baggage();
+        Ok(format!(
+            "{} {}",
+            baggage.get("tenant").map(|value| value.as_str()).unwrap_or_default(),
+            baggage.get("user").map(|value| value.as_str()).unwrap_or_default()
+        ))
+    });
+
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    req.append_header("baggage", "tenant=acme");
+    req.append_header("baggage", "user=alice");
+    let mut res: tide::http::Response = app.respond(req).await.expect("response");
+
+    assert_eq!(res.body_string().await.expect("body"), "acme alice");
+}
+
+#[async_std::test]
+async fn extract_and_inject_propagators_are_separate() {
+    let traces = Traces::new();
+    let config = TracingConfig {
+        extract_propagator: Some(Arc::new(TraceContextPropagator::new())),
+        inject_propagator: Some(Arc::new(opentelemetry_jaeger::Propagator::new())),
+        ..Default::default()
+    };
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(traces.tracer(), config));
+    app.at("/*").get(|_| async { Ok("") });
+
+    // continued from the W3C headers, answered in the Jaeger format
+    let mut req = request(Method::Get, "/w3c");
+    req.insert_header("traceparent", TRACEPARENT);
+    let res: tide::http::Response = app.respond(req).await.expect("response");
+    let span = traces.span("GET http://localhost/w3c");
+    assert_eq!(
+        span.span_context.trace_id().to_string(),
+        "0af7651916cd43dd8448eb211c80319c"
+    );
+    assert!(res.header("traceparent").is_none());
+    let uber_trace_id = res.header("uber-trace-id").expect("jaeger header").last().to_string();
+    assert_eq!(
+        uber_trace_id,
+        format!("0af7651916cd43dd8448eb211c80319c:{}:0:1", span.span_context.span_id())
+    );
+
+    // the Jaeger format is not extracted
+    let mut req = request(Method::Get, "/jaeger");
+    req.insert_header("uber-trace-id", uber_trace_id.as_str());
+    let _: tide::http::Response = app.respond(req).await.expect("response");
+    let span = traces.span("GET http://localhost/jaeger");
+    assert_ne!(
+        span.span_context.trace_id().to_string(),
+        "0af7651916cd43dd8448eb211c80319c"
+    );
+    assert_eq!(span.parent_span_id, SpanId::INVALID);
+}
+
+#[cfg(any(feature = "propagator-b3", feature = "propagator-xray"))]
+mod presets {
+    use opentelemetry::{
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+        Context,
+    };
+    use opentelemetry_tide::propagation::by_name;
+    use std::collections::HashMap;
+
+    pub(super) fn extract(name: &str, headers: &[(&str, &str)]) -> SpanContext {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let cx = by_name(name).expect("propagator").extract(&headers);
+        let span = cx.span();
+        span.span_context().clone()
+    }
+
+    pub(super) fn inject(name: &str, span_context: SpanContext) -> HashMap<String, String> {
+        let cx = Context::new().with_remote_span_context(span_context);
+        let mut headers = HashMap::new();
+        by_name(name).expect("propagator").inject_context(&cx, &mut headers);
+        headers
+    }
+
+    pub(super) fn span_context(trace_id: &str, span_id: &str, trace_flags: u8) -> SpanContext {
+        SpanContext::new(
+            TraceId::from_hex(trace_id).expect("trace id"),
+            SpanId::from_hex(span_id).expect("span id"),
+            TraceFlags::new(trace_flags),
+            true,
+            TraceState::default(),
+        )
+    }
+}
+
+#[cfg(feature = "propagator-b3")]
+mod b3 {
+    use super::presets::{extract, inject, span_context};
+    use opentelemetry::trace::TraceFlags;
+    use std::collections::HashMap;
+
+    const TRACE_ID: &str = "80f198ee56343ba864fe8b2a57d3eff7";
+    const SPAN_ID: &str = "e457b5a2e4d86bd1";
+    const DEFERRED: u8 = 0x02;
+    const DEBUG: u8 = 0x04;
+
+    #[test]
+    fn single_header() {
+        let value = format!("{}-{}-1-05e3ac9a4f6e3b90", TRACE_ID, SPAN_ID);
+        let extracted = extract("b3", &[("b3", &value)]);
+        assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, 1));
+
+        // opentelemetry-zipkin injects the multiple headers alongside the single one
+        let injected = inject("b3", extracted);
+        let expected: HashMap<String, String> = [
+            ("b3".to_owned(), format!("{}-{}-1", TRACE_ID, SPAN_ID)),
+            ("x-b3-traceid".to_owned(), TRACE_ID.to_owned()),
+            ("x-b3-spanid".to_owned(), SPAN_ID.to_owned()),
+            ("x-b3-sampled".to_owned(), "1".to_owned()),
+        ]
+        .into();
+        assert_eq!(injected, expected);
+    }
+
+    #[test]
+    fn multiple_headers() {
+        let headers = [
+            ("x-b3-traceid", TRACE_ID),
+            ("x-b3-spanid", SPAN_ID),
+            ("x-b3-parentspanid", "05e3ac9a4f6e3b90"),
+            ("x-b3-sampled", "1"),
+        ];
+        let extracted = extract("b3multi", &headers);
+        assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, 1));
+
+        let injected = inject("b3multi", extracted);
+        let expected: HashMap<String, String> = [
+            ("x-b3-traceid".to_owned(), TRACE_ID.to_owned()),
+            ("x-b3-spanid".to_owned(), SPAN_ID.to_owned()),
+            ("x-b3-sampled".to_owned(), "1".to_owned()),
+        ]
+        .into();
+        assert_eq!(injected, expected);
+    }
+
+    #[test]
+    fn single_header_is_extracted_by_the_multiple_headers_propagator_as_well() {
+        let value = format!("{}-{}-0", TRACE_ID, SPAN_ID);
+        assert_eq!(
+            extract("b3multi", &[("b3", &value)]),
+            span_context(TRACE_ID, SPAN_ID, 0)
+        );
+    }
+
+    #[test]
+    fn short_trace_ids_are_padded() {
+        let value = format!("a3ce929d0e0e4736-{}-1", SPAN_ID);
+        let extracted = extract("b3", &[("b3", &value)]);
+        assert_eq!(extracted.trace_id().to_string(), "0000000000000000a3ce929d0e0e4736");
+        assert!(extracted.is_sampled());
+
+        let headers = [("x-b3-traceid", "a3ce929d0e0e4736"), ("x-b3-spanid", SPAN_ID)];
+        assert_eq!(
+            extract("b3multi", &headers).trace_id().to_string(),
+            "0000000000000000a3ce929d0e0e4736"
+        );
+    }
+
+    #[test]
+    fn missing_sampling_decision_is_deferred() {
+        let value = format!("{}-{}", TRACE_ID, SPAN_ID);
+        let extracted = extract("b3", &[("b3", &value)]);
+        assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, DEFERRED));
+        assert!(!extracted.is_sampled());
+
+        // and passed on as such
+        let injected = inject("b3", extracted);
+        assert_eq!(injected.get("b3"), Some(&value));
+    }
+
+    #[test]
+    fn debug_flag() {
+        let value = format!("{}-{}-d", TRACE_ID, SPAN_ID);
+        let extracted = extract("b3", &[("b3", &value)]);
+        assert_eq!(extracted.trace_flags() & TraceFlags::new(DEBUG), TraceFlags::new(DEBUG));
+        assert_eq!(inject("b3", extracted).get("b3"), Some(&value));
+
+        // implies sampled in the multiple headers
+        let headers = [
+            ("x-b3-traceid", TRACE_ID),
+            ("x-b3-spanid", SPAN_ID),
+            ("x-b3-flags", "1"),
+        ];
+        let extracted = extract("b3multi", &headers);
+        assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, DEBUG | 1));
+        assert_eq!(
+            inject("b3multi", extracted).get("x-b3-flags").map(String::as_str),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn sampling_decision_alone_carries_no_context() {
+        assert!(!extract("b3", &[("b3", "0")]).is_valid());
+        assert!(!extract("b3", &[("b3", "1")]).is_valid());
+        assert!(!extract("b3multi", &[("x-b3-sampled", "0")]).is_valid());
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for value in [
+            "",
+            "-",
+            TRACE_ID,
+            &format!("{}-{}", TRACE_ID.to_uppercase(), SPAN_ID),
+            &format!("{}-{}-x", TRACE_ID, SPAN_ID),
+            &format!("{}-e457b5a2", TRACE_ID),
+            &format!("{}-{}-1-05e3ac9a4f6e3b90-extra", TRACE_ID, SPAN_ID),
+        ] {
+            assert!(!extract("b3", &[("b3", value)]).is_valid(), "{:?}", value);
+        }
+    }
+}
+
+#[cfg(feature = "propagator-xray")]
+mod xray {
+    use super::presets::{extract, inject, span_context};
+
+    const HEADER: &str = "x-amzn-trace-id";
+    const TRACE_ID: &str = "5759e988bd862e3fe1be46a994272793";
+    const SPAN_ID: &str = "53995c3f42cd8ad8";
+
+    #[test]
+    fn root_parent_and_sampled_are_parsed() {
+        let value = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+        let extracted = extract("xray", &[(HEADER, value)]);
+        assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, 1));
+        assert!(extracted.is_remote());
+
+        assert_eq!(inject("xray", extracted).get(HEADER).map(String::as_str), Some(value));
+    }
+
+    #[test]
+    fn sampling_decision() {
+        let unsampled = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=0";
+        assert_eq!(
+            extract("xray", &[(HEADER, unsampled)]),
+            span_context(TRACE_ID, SPAN_ID, 0)
+        );
+
+        // requested or missing decisions are deferred
+        for value in [
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=?",
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8",
+        ] {
+            let extracted = extract("xray", &[(HEADER, value)]);
+            assert_eq!(extracted, span_context(TRACE_ID, SPAN_ID, 0x02));
+            assert!(!extracted.is_sampled());
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for value in [
+            "",
+            "Root=",
+            "Root=1-5759e988;Parent=53995c3f42cd8ad8;Sampled=1",
+            "Root=1-5759e988-bd862e3fe1be46a99427279z;Parent=53995c3f42cd8ad8;Sampled=1",
+            "Root=1-00000000-000000000000000000000000;Parent=53995c3f42cd8ad8;Sampled=1",
+            "Parent=53995c3f42cd8ad8;Sampled=1",
+        ] {
+            assert!(!extract("xray", &[(HEADER, value)]).is_valid(), "{:?}", value);
+        }
+    }
+}
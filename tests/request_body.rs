@@ -0,0 +1,116 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, event_names, metric_value, metrics_middleware, request, scrape, Traces};
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryTracingMiddleware, TracingConfig};
+use std::time::Duration;
+use tide::http::{Method, Response};
+
+const BODY_SIZE: usize = 12_345;
+const HANDLER_DELAY: Duration = Duration::from_millis(50);
+
+fn upload_app() -> tide::Server<()> {
+    let mut app = tide::new();
+    app.at("/upload").post(|mut req: tide::Request<()>| async move {
+        // time spent in the handler before reading does not count as read time
+        async_std::task::sleep(HANDLER_DELAY).await;
+        let body = req.body_bytes().await?;
+        Ok(body.len().to_string())
+    });
+    app.at("/ignore").post(|_| async { Ok("") });
+    app
+}
+
+async fn upload(app: &tide::Server<()>, path: &str) -> Response {
+    let mut req = request(Method::Post, path);
+    req.set_body(vec![1u8; BODY_SIZE]);
+    app.respond(req).await.expect("response")
+}
+
+fn traced_app(traces: &Traces) -> tide::Server<()> {
+    let mut app = upload_app();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(
+        traces.tracer(),
+        TracingConfig {
+            track_request_body: true,
+            ..Default::default()
+        },
+    ));
+    app
+}
+
+#[async_std::test]
+async fn read_body_is_recorded_on_the_span() {
+    let traces = Traces::new();
+    let app = traced_app(&traces);
+
+    let mut res = upload(&app, "/upload").await;
+    assert_eq!(res.body_string().await.expect("body"), BODY_SIZE.to_string());
+
+    let span = traces.span("POST http://localhost/upload");
+    assert_eq!(
+        attribute(&span, "http.request_content_length"),
+        Some((BODY_SIZE as i64).into())
+    );
+    let read_time_ms = match attribute(&span, "http.request_body_read_time_ms") {
+        Some(opentelemetry::Value::F64(read_time_ms)) => read_time_ms,
+        other => panic!("unexpected read time {:?}", other),
+    };
+    assert!(
+        read_time_ms < HANDLER_DELAY.as_secs_f64() * 1_000f64,
+        "{}",
+        read_time_ms
+    );
+    assert!(event_names(&span).contains(&"request.body.finished".to_owned()));
+}
+
+#[async_std::test]
+async fn untouched_body_is_not_recorded() {
+    let traces = Traces::new();
+    let app = traced_app(&traces);
+
+    let _ = upload(&app, "/ignore").await;
+
+    let span = traces.span("POST http://localhost/ignore");
+    assert_eq!(attribute(&span, "http.request_body_read_time_ms"), None);
+    assert!(!event_names(&span).iter().any(|name| name.starts_with("request.body")));
+}
+
+#[async_std::test]
+async fn read_body_is_recorded_in_the_metrics() {
+    let mut app = upload_app();
+    app.with(metrics_middleware(MetricsConfig {
+        track_request_body: true,
+        ..Default::default()
+    }));
+
+    let _ = upload(&app, "/upload").await;
+    let _ = upload(&app, "/ignore").await;
+
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(&metrics, &["http_server_request_body_bytes{", "http_route=\"/upload\""]),
+        Some(BODY_SIZE as f64),
+        "{}",
+        metrics
+    );
+    assert_eq!(
+        metric_value(
+            &metrics,
+            &[
+                "http_server_request_body_read_duration_seconds_count{",
+                "http_route=\"/upload\""
+            ]
+        ),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+    assert_eq!(
+        metric_value(&metrics, &["http_server_request_body_bytes{", "http_route=\"/ignore\""]),
+        None,
+        "{}",
+        metrics
+    );
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{attribute, request, Traces};
+use opentelemetry_tide::{
+    OpenTelemetryTracingMiddleware, RequestId, RequestIdConfig, RequestIdGenerator, RequestIdMiddleware,
+};
+use std::sync::Arc;
+use tide::http::Method;
+
+fn app(traces: &Traces, config: RequestIdConfig) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.with(RequestIdMiddleware::new_with_config(config));
+    app.at("/").get(|req: tide::Request<()>| async move {
+        Ok(req.ext::<RequestId>().map(ToString::to_string).unwrap_or_default())
+    });
+    app
+}
+
+/// Sends a request with the given request id header, returns the echoed header and the id seen by the handler
+async fn send(app: &tide::Server<()>, header: Option<(&str, &str)>) -> (Option<String>, String) {
+    let mut req = request(Method::Get, "/");
+    if let Some((name, value)) = header {
+        req.insert_header(name, value);
+    }
+    let mut res: tide::http::Response = app.respond(req).await.expect("response");
+    let echoed = res.header("x-request-id").map(|values| values.last().to_string());
+    (echoed, res.body_string().await.expect("body"))
+}
+
+#[async_std::test]
+async fn request_id_is_generated() {
+    let traces = Traces::new();
+    let app = app(&traces, RequestIdConfig::default());
+
+    let (echoed, seen) = send(&app, None).await;
+    assert_eq!(seen.len(), 32);
+    assert!(seen.chars().all(|c| c.is_ascii_hexdigit()), "{}", seen);
+    assert_eq!(echoed.as_deref(), Some(seen.as_str()));
+
+    let span = traces.span("GET http://localhost/");
+    assert_eq!(attribute(&span, "http.request_id"), Some(seen.clone().into()));
+
+    let (_, other) = send(&app, None).await;
+    assert_ne!(seen, other);
+}
+
+#[async_std::test]
+async fn incoming_request_id_is_echoed() {
+    let traces = Traces::new();
+    let app = app(&traces, RequestIdConfig::default());
+
+    let (echoed, seen) = send(&app, Some(("X-Request-Id", "abc-123"))).await;
+    assert_eq!(seen, "abc-123");
+    assert_eq!(echoed.as_deref(), Some("abc-123"));
+}
+
+#[async_std::test]
+async fn malformed_incoming_request_id_is_replaced() {
+    let traces = Traces::new();
+    let app = app(&traces, RequestIdConfig::default());
+
+    let too_long = "a".repeat(129);
+    for malformed in ["with space", "", too_long.as_str()] {
+        let (echoed, seen) = send(&app, Some(("x-request-id", malformed))).await;
+        assert_ne!(seen, malformed);
+        assert_eq!(seen.len(), 32);
+        assert_eq!(echoed.as_deref(), Some(seen.as_str()));
+    }
+}
+
+#[async_std::test]
+async fn incoming_request_id_is_ignored_unless_trusted() {
+    let traces = Traces::new();
+    let config = RequestIdConfig {
+        header: "x-correlation-id".parse().expect("header name"),
+        generator: RequestIdGenerator::Custom(Arc::new(|| "generated".to_owned())),
+        trust_incoming: false,
+    };
+    let app = app(&traces, config);
+
+    let mut req = request(Method::Get, "/");
+    req.insert_header("x-correlation-id", "from-client");
+    let mut res: tide::http::Response = app.respond(req).await.expect("response");
+    assert_eq!(res.body_string().await.expect("body"), "generated");
+    assert_eq!(
+        res.header("x-correlation-id").map(|values| values.last().as_str()),
+        Some("generated")
+    );
+    assert!(res.header("x-request-id").is_none());
+}
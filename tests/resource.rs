@@ -0,0 +1,88 @@
+use opentelemetry::{sdk::Resource, Key, Value};
+use std::{
+    env,
+    sync::{Mutex, MutexGuard},
+};
+
+// the detectors read the environment of the process, which is shared by the tests
+static ENV: Mutex<()> = Mutex::new(());
+
+const VARS: [&str; 8] = [
+    "OTEL_SERVICE_NAME",
+    "OTEL_RESOURCE_ATTRIBUTES",
+    "K8S_POD_NAME",
+    "K8S_NAMESPACE_NAME",
+    "K8S_NODE_NAME",
+    "POD_NAME",
+    "POD_NAMESPACE",
+    "NODE_NAME",
+];
+
+/// Locks and cleans the environment
+fn clean_env() -> MutexGuard<'static, ()> {
+    let lock = match ENV.lock() {
+        Ok(lock) => lock,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    for var in VARS {
+        env::remove_var(var);
+    }
+    lock
+}
+
+fn get(resource: &Resource, key: &'static str) -> Option<Value> {
+    resource.get(Key::from_static_str(key))
+}
+
+#[test]
+fn macro_takes_the_service_from_the_cargo_package() {
+    let _env = clean_env();
+    let resource = opentelemetry_tide::detect_resource!();
+    assert_eq!(get(&resource, "service.name"), Some(env!("CARGO_PKG_NAME").into()));
+    assert_eq!(
+        get(&resource, "service.version"),
+        Some(env!("CARGO_PKG_VERSION").into())
+    );
+    assert_eq!(
+        get(&resource, "process.pid"),
+        Some(i64::from(std::process::id()).into())
+    );
+}
+
+#[test]
+fn service_name_falls_back_to_unknown_service() {
+    let _env = clean_env();
+    let resource = opentelemetry_tide::resource::detect(None, None);
+    assert_eq!(get(&resource, "service.name"), Some("unknown_service".into()));
+    assert_eq!(get(&resource, "service.version"), None);
+}
+
+#[test]
+fn environment_variables_take_precedence() {
+    let _env = clean_env();
+    env::set_var(
+        "OTEL_RESOURCE_ATTRIBUTES",
+        "service.name=from-attributes,deployment.environment=staging",
+    );
+    let resource = opentelemetry_tide::resource::detect(Some("from-cargo"), Some("1.0.0"));
+    assert_eq!(get(&resource, "service.name"), Some("from-attributes".into()));
+    assert_eq!(get(&resource, "deployment.environment"), Some("staging".into()));
+    assert_eq!(get(&resource, "service.version"), Some("1.0.0".into()));
+
+    env::set_var("OTEL_SERVICE_NAME", "from-service-name");
+    let resource = opentelemetry_tide::resource::detect(Some("from-cargo"), None);
+    assert_eq!(get(&resource, "service.name"), Some("from-service-name".into()));
+}
+
+#[test]
+fn kubernetes_attributes_from_the_downward_api() {
+    let _env = clean_env();
+    env::set_var("POD_NAME", "shop-7d9f");
+    env::set_var("K8S_POD_NAME", "shop-1a2b");
+    env::set_var("POD_NAMESPACE", "production");
+    let resource = opentelemetry_tide::resource::detect(None, None);
+    // the K8S_* naming scheme wins
+    assert_eq!(get(&resource, "k8s.pod.name"), Some("shop-1a2b".into()));
+    assert_eq!(get(&resource, "k8s.namespace.name"), Some("production".into()));
+    assert_eq!(get(&resource, "k8s.node.name"), None);
+}
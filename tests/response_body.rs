@@ -0,0 +1,107 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, event_names, get, metric_value, metrics_middleware, scrape, Traces};
+use opentelemetry::trace::StatusCode;
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryTracingMiddleware, TracingConfig};
+use std::time::Duration;
+
+const BODY_SIZE: usize = 100_000;
+const CLIENT_DELAY: Duration = Duration::from_millis(50);
+
+fn streaming_app() -> tide::Server<()> {
+    let mut app = tide::new();
+    app.at("/download").get(|_| async {
+        let mut res = tide::Response::new(200);
+        res.set_body(tide::Body::from_reader(
+            async_std::io::Cursor::new(vec![1u8; BODY_SIZE]),
+            None,
+        ));
+        Ok(res)
+    });
+    app
+}
+
+fn traced_app(traces: &Traces) -> tide::Server<()> {
+    let mut app = streaming_app();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(
+        traces.tracer(),
+        TracingConfig {
+            track_response_body: true,
+            ..Default::default()
+        },
+    ));
+    app
+}
+
+#[async_std::test]
+async fn span_ends_when_the_body_has_been_sent() {
+    let traces = Traces::new();
+    let app = traced_app(&traces);
+
+    let mut res = get(&app, "/download").await;
+    assert!(traces.spans().is_empty(), "span ended before the body was sent");
+
+    async_std::task::sleep(CLIENT_DELAY).await;
+    assert_eq!(res.body_bytes().await.expect("body").len(), BODY_SIZE);
+
+    let span = traces.span("GET http://localhost/download");
+    assert_eq!(span.status_code, StatusCode::Ok);
+    assert_eq!(
+        attribute(&span, "http.response_content_length"),
+        Some((BODY_SIZE as i64).into())
+    );
+    let events = event_names(&span);
+    let headers_ready = events.iter().position(|name| name == "request.finished");
+    let body_sent = events.iter().position(|name| name == "response.body.finished");
+    assert!(headers_ready.is_some() && headers_ready < body_sent, "{:?}", events);
+
+    let duration = span.end_time.duration_since(span.start_time).expect("duration");
+    assert!(duration >= CLIENT_DELAY, "{:?}", duration);
+}
+
+#[async_std::test]
+async fn span_ends_as_cancelled_when_the_body_is_dropped() {
+    let traces = Traces::new();
+    let app = traced_app(&traces);
+
+    drop(get(&app, "/download").await);
+
+    let span = traces.span("GET http://localhost/download");
+    assert_eq!(span.status_code, StatusCode::Error);
+    assert_eq!(attribute(&span, "http.cancelled"), Some(true.into()));
+    assert!(event_names(&span).contains(&"response.body.cancelled".to_owned()));
+}
+
+#[async_std::test]
+async fn duration_is_recorded_when_the_body_has_been_sent() {
+    let mut app = streaming_app();
+    app.with(metrics_middleware(MetricsConfig {
+        track_response_body: true,
+        ..Default::default()
+    }));
+
+    let mut res = get(&app, "/download").await;
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(&metrics, &["http_server_requests_count{"]),
+        None,
+        "{}",
+        metrics
+    );
+
+    async_std::task::sleep(CLIENT_DELAY).await;
+    let _ = res.body_bytes().await.expect("body");
+
+    let metrics = scrape(&app, "/metrics").await;
+    let labels = "http_method=\"GET\",http_route=\"/download\",http_status_code=\"200\"";
+    assert_eq!(
+        metric_value(&metrics, &["http_server_requests_count{", labels]),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+    let duration = metric_value(&metrics, &["http_server_request_duration_seconds_sum{", labels]).expect("duration");
+    assert!(duration >= CLIENT_DELAY.as_secs_f64(), "{}", metrics);
+}
@@ -0,0 +1,182 @@
+#![cfg(all(feature = "trace", feature = "metrics"))]
+
+mod common;
+
+use common::{attribute, get, metric_value, metrics_middleware, scrape, Traces};
+use opentelemetry_tide::{MetricsConfig, OpenTelemetryMetricsMiddleware, OpenTelemetryTracingMiddleware, TideExt};
+
+fn users() -> tide::Server<()> {
+    let mut inner = tide::new();
+    inner.at("/users/:id").get(|_| async { Ok("user") });
+    inner
+}
+
+#[async_std::test]
+async fn nesting_route_reports_the_prefix_with_a_wildcard() {
+    let traces = Traces::new();
+    let mut app = tide::new();
+    let _ = app.at("/api").with_tracing_middleware(traces.tracer()).nest(users());
+
+    let res = get(&app, "/api/users/1").await;
+    assert_eq!(res.status(), 200);
+
+    let span = traces.span("GET /api/*");
+    assert_eq!(attribute(&span, "http.route"), Some("/api/*".into()));
+    assert_eq!(attribute(&span, "http.target"), Some("/api/users/1".into()));
+}
+
+#[async_std::test]
+async fn nested_route_middleware_reports_the_prefixed_template() {
+    let traces = Traces::new();
+    let mut inner = tide::new();
+    let _ = inner
+        .at("/users/:id")
+        .with_tracing_middleware(traces.tracer())
+        .get(|_| async { Ok("user") });
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    let _ = app.at("/api").nest(inner);
+
+    let res = get(&app, "/api/users/1").await;
+    assert_eq!(res.status(), 200);
+
+    let outer = traces.span("GET http://localhost/api/users/1");
+    assert_eq!(attribute(&outer, "http.route"), None);
+    let inner = traces.span("GET /api/users/:id");
+    assert_eq!(attribute(&inner, "http.route"), Some("/api/users/:id".into()));
+    // the url is reported as requested, not with the prefix stripped off
+    assert_eq!(
+        attribute(&inner, "http.url"),
+        Some("http://localhost/api/users/1".into())
+    );
+    assert_eq!(inner.parent_span_id, outer.span_context.span_id());
+}
+
+#[async_std::test]
+async fn nested_server_middleware_reports_the_prefixed_path() {
+    let traces = Traces::new();
+    let mut inner = users();
+    inner.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    let mut app = tide::new();
+    let _ = app.at("/api").with_tracing_middleware(traces.tracer()).nest(inner);
+
+    let _ = get(&app, "/api/users/1").await;
+
+    let outer = traces.span("GET /api/*");
+    let inner = traces.span("GET http://localhost/api/users/1");
+    assert_eq!(attribute(&inner, "http.route"), None);
+    assert_eq!(attribute(&inner, "http.target"), Some("/api/users/1".into()));
+    assert_eq!(inner.parent_span_id, outer.span_context.span_id());
+}
+
+#[async_std::test]
+async fn nested_server_metrics_report_the_prefixed_path() {
+    let traces = Traces::new();
+    let mut inner = users();
+    inner.with(metrics_middleware(MetricsConfig::default()));
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    let _ = app.at("/api").nest(inner);
+
+    let _ = get(&app, "/api/users/1").await;
+    let metrics = scrape(&app, "/api/metrics").await;
+    assert_eq!(
+        metric_value(
+            &metrics,
+            &[
+                "http_server_request_duration_seconds_count{",
+                "http_route=\"/api/users/1\""
+            ]
+        ),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+}
+
+#[async_std::test]
+async fn combined_middleware_reports_the_same_route() {
+    let traces = Traces::new();
+    let tracer = traces.tracer();
+    let mut app = tide::new();
+    common::with_global_providers(|| {
+        app.with(OpenTelemetryMetricsMiddleware::new(MetricsConfig::default()));
+        let _ = app
+            .at("/api")
+            .with_combined_middleware(tracer, MetricsConfig::default())
+            .nest(users());
+    });
+
+    let _ = get(&app, "/api/users/1").await;
+    let span = traces.span("GET /api/*");
+    assert_eq!(attribute(&span, "http.route"), Some("/api/*".into()));
+
+    let metrics = scrape(&app, "/metrics").await;
+    assert_eq!(
+        metric_value(
+            &metrics,
+            &["http_server_request_duration_seconds_count{", "http_route=\"/api/*\""]
+        ),
+        Some(1.0),
+        "{}",
+        metrics
+    );
+}
+
+#[async_std::test]
+async fn route_metrics_are_served_by_the_app_metrics_middleware() {
+    let mut app = tide::new();
+    common::with_global_providers(|| {
+        app.with(OpenTelemetryMetricsMiddleware::new(MetricsConfig::default()));
+        let _ = app
+            .at("/users/:id")
+            .with_metrics_middleware(MetricsConfig::default())
+            .get(|_| async { Ok("user") });
+        let _ = app
+            .at("/orders/:id")
+            .with_metrics_middleware(MetricsConfig::default())
+            .get(|_| async { Ok("order") });
+    });
+
+    let _ = get(&app, "/users/1").await;
+    let _ = get(&app, "/orders/1").await;
+    // the scraping route stays as configured, not relative to the routes
+    assert_eq!(get(&app, "/users/:id/metrics").await.status(), 404);
+
+    let metrics = scrape(&app, "/metrics").await;
+    for route in ["/users/:id", "/orders/:id"] {
+        let label = format!("http_route=\"{}\"", route);
+        assert_eq!(
+            metric_value(&metrics, &["http_server_request_duration_seconds_count{", &label]),
+            Some(1.0),
+            "{}",
+            metrics
+        );
+    }
+}
+
+#[async_std::test]
+async fn templated_nesting_route_is_the_prefix_of_the_nested_routes() {
+    let traces = Traces::new();
+    let mut inner = tide::new();
+    let _ = inner
+        .at("/users/:id")
+        .with_tracing_middleware(traces.tracer())
+        .get(|_| async { Ok("user") });
+    let mut app = tide::new();
+    let _ = app
+        .at("/tenants/:tenant")
+        .with_tracing_middleware(traces.tracer())
+        .nest(inner);
+
+    let res = get(&app, "/tenants/acme/users/1").await;
+    assert_eq!(res.status(), 200);
+
+    let outer = traces.span("GET /tenants/:tenant/*");
+    let inner = traces.span("GET /tenants/:tenant/users/:id");
+    assert_eq!(
+        attribute(&inner, "http.route"),
+        Some("/tenants/:tenant/users/:id".into())
+    );
+    assert_eq!(inner.parent_span_id, outer.span_context.span_id());
+}
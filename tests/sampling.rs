@@ -0,0 +1,249 @@
+#![cfg(feature = "trace")]
+
+mod common;
+
+use common::{attribute, get, request, Traces};
+use opentelemetry::{
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{Sampler, SamplingDecision, SamplingResult, ShouldSample},
+        InstrumentationLibrary,
+    },
+    trace::{Link, SpanKind, TraceId, TraceState},
+    Context, KeyValue,
+};
+use opentelemetry_tide::{
+    DebugTraceConfig, OpenTelemetryTracingMiddleware, RouteSampling, SamplingRule, TideExt, TracingConfig,
+};
+use std::sync::{Arc, Mutex};
+use tide::http::Method;
+
+const UNSAMPLED_PARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00";
+
+fn app(traces: &Traces, config: TracingConfig) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new_with_config(traces.tracer(), config));
+    app.at("/*").get(|_| async { Ok("") });
+    app
+}
+
+/// Counts the recorded server spans of the path
+fn sampled(traces: &Traces, path: &str) -> usize {
+    let name = format!("GET http://localhost{}", path);
+    traces.spans().iter().filter(|span| span.name == name).count()
+}
+
+#[async_std::test]
+async fn first_matching_rule_decides() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let config = TracingConfig {
+        sampling_rules: vec![
+            SamplingRule::new("/checkout", RouteSampling::Always),
+            SamplingRule::new("/static/private/*", RouteSampling::Always),
+            SamplingRule::new("/static/*", RouteSampling::Never),
+        ],
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    for path in ["/checkout", "/static/app.css", "/static/private/key", "/other"] {
+        let _ = get(&app, path).await;
+    }
+
+    assert_eq!(sampled(&traces, "/checkout"), 1);
+    assert_eq!(sampled(&traces, "/static/app.css"), 0);
+    assert_eq!(sampled(&traces, "/static/private/key"), 1);
+    // left to the sampler of the tracer provider
+    assert_eq!(sampled(&traces, "/other"), 0);
+}
+
+#[async_std::test]
+async fn paths_matching_a_rate_limit_rule_share_its_budget() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let config = TracingConfig {
+        sampling_rules: vec![SamplingRule::new("/api/*", RouteSampling::RateLimit(1.0))],
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    // without the route template, distinct paths must not get a budget of their own each
+    for id in 0..200 {
+        let _ = get(&app, &format!("/api/users/{}", id)).await;
+    }
+
+    let sampled = traces
+        .spans()
+        .iter()
+        .filter(|span| span.name.starts_with("GET http://localhost/api/users/"))
+        .count();
+    assert_eq!(sampled, 1);
+}
+
+fn debug_app(traces: &Traces) -> tide::Server<()> {
+    let config = TracingConfig {
+        debug_trace: Some(DebugTraceConfig::new("s3cret")),
+        extract_propagator: Some(Arc::new(TraceContextPropagator::new())),
+        ..Default::default()
+    };
+    app(traces, config)
+}
+
+/// Sends a request with the given debug header and traceparent, returns the `x-trace-id` response header
+async fn debug_request(
+    app: &tide::Server<()>,
+    path: &str,
+    secret: Option<&str>,
+    parent: Option<&str>,
+) -> Option<String> {
+    let mut req = request(Method::Get, path);
+    if let Some(secret) = secret {
+        req.insert_header("x-debug-trace", secret);
+    }
+    if let Some(parent) = parent {
+        req.insert_header("traceparent", parent);
+    }
+    let res: tide::http::Response = app.respond(req).await.expect("response");
+    res.header("x-trace-id").map(|values| values.last().to_string())
+}
+
+#[async_std::test]
+async fn debug_header_forces_sampling() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let app = debug_app(&traces);
+
+    let trace_id = debug_request(&app, "/debug", Some("s3cret"), None).await;
+    let span = traces.span("GET http://localhost/debug");
+    assert!(span.span_context.is_sampled());
+    assert_eq!(trace_id, Some(span.span_context.trace_id().to_string()));
+}
+
+#[async_std::test]
+async fn debug_header_overrides_an_unsampled_parent() {
+    let traces = Traces::with_sampler(Sampler::ParentBased(Box::new(Sampler::AlwaysOn)));
+    let app = debug_app(&traces);
+
+    let _ = debug_request(&app, "/unsampled", None, Some(UNSAMPLED_PARENT)).await;
+    assert_eq!(sampled(&traces, "/unsampled"), 0);
+
+    let trace_id = debug_request(&app, "/forced", Some("s3cret"), Some(UNSAMPLED_PARENT)).await;
+    let span = traces.span("GET http://localhost/forced");
+    // the trace of the caller is continued
+    assert_eq!(trace_id.as_deref(), Some("0af7651916cd43dd8448eb211c80319c"));
+    assert_eq!(
+        span.span_context.trace_id().to_string(),
+        "0af7651916cd43dd8448eb211c80319c"
+    );
+    assert_eq!(span.parent_span_id.to_string(), "b7ad6b7169203331");
+}
+
+#[async_std::test]
+async fn debug_header_with_a_wrong_secret_is_ignored() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let app = debug_app(&traces);
+
+    for secret in ["wrong", "s3cre", "s3cret ", ""] {
+        assert_eq!(debug_request(&app, "/wrong", Some(secret), None).await, None);
+    }
+    assert_eq!(debug_request(&app, "/wrong", None, None).await, None);
+    assert_eq!(sampled(&traces, "/wrong"), 0);
+}
+
+#[async_std::test]
+async fn empty_secret_authorises_nobody() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let config = TracingConfig {
+        debug_trace: Some(DebugTraceConfig::new("")),
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    assert_eq!(debug_request(&app, "/empty", Some(""), None).await, None);
+    assert_eq!(sampled(&traces, "/empty"), 0);
+}
+
+#[async_std::test]
+async fn sampled_requests_without_debug_header_get_no_trace_id_header() {
+    let traces = Traces::new();
+    let app = debug_app(&traces);
+
+    assert_eq!(debug_request(&app, "/sampled", None, None).await, None);
+    assert_eq!(sampled(&traces, "/sampled"), 1);
+}
+
+/// A span name and the attributes, as seen by the sampler
+type Seen = (String, Vec<KeyValue>);
+
+/// Records the span names and attributes it gets to see, and samples nothing
+#[derive(Debug, Clone, Default)]
+struct SeenBySampler(Arc<Mutex<Vec<Seen>>>);
+
+impl ShouldSample for SeenBySampler {
+    fn should_sample(
+        &self,
+        _parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+        _instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        if let Ok(mut seen) = self.0.lock() {
+            seen.push((name.to_owned(), attributes.to_vec()));
+        }
+        SamplingResult {
+            decision: SamplingDecision::Drop,
+            attributes: Vec::new(),
+            trace_state: TraceState::default(),
+        }
+    }
+}
+
+#[async_std::test]
+async fn sampler_sees_the_known_values() {
+    let sampler = SeenBySampler::default();
+    let traces = Traces::with_sampler(sampler.clone());
+    let mut templated = tide::new();
+    templated
+        .at("/users/:id")
+        .with_tracing_middleware(traces.tracer())
+        .get(|_| async { Ok("") });
+    let _ = get(&templated, "/users/42").await;
+    let mut app = tide::new();
+    app.with_tracing_middleware(traces.tracer());
+    app.at("/users/:id").get(|_| async { Ok("") });
+    let _ = get(&app, "/users/42").await;
+
+    let seen = sampler.0.lock().expect("seen").clone();
+    assert_eq!(
+        seen,
+        vec![
+            (
+                "GET /users/:id".to_owned(),
+                vec![
+                    KeyValue::new("http.method", "GET"),
+                    KeyValue::new("http.route", "/users/:id")
+                ]
+            ),
+            // without the route template the name needs the url, which is only built for recording spans
+            ("HTTP request".to_owned(), vec![KeyValue::new("http.method", "GET")]),
+        ]
+    );
+}
+
+#[async_std::test]
+async fn spans_sampled_by_a_rule_get_the_known_values() {
+    let traces = Traces::with_sampler(Sampler::AlwaysOff);
+    let config = TracingConfig {
+        sampling_rules: vec![SamplingRule::new("/users/*", RouteSampling::Always)],
+        ..Default::default()
+    };
+    let app = app(&traces, config);
+
+    let _ = get(&app, "/users/42").await;
+
+    // the sampler of the tracer provider was not asked, so the known values are set on the recording span
+    let span = traces.span("GET http://localhost/users/42");
+    assert_eq!(attribute(&span, "http.method"), Some("GET".into()));
+    assert_eq!(attribute(&span, "http.target"), Some("/users/42".into()));
+}
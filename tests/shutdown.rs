@@ -0,0 +1,256 @@
+#![cfg(feature = "shutdown")]
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    io::{prelude::*, BufReader},
+    net::TcpStream,
+    task,
+};
+use opentelemetry_tide::shutdown::{listen_until, ShutdownConfig};
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tide::{Middleware, Next, Request};
+
+/// A raw HTTP/1.1 connection, so keep-alive can be observed
+struct Connection(BufReader<TcpStream>);
+
+impl Connection {
+    async fn open(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).await.expect("connection");
+        Self(BufReader::new(stream))
+    }
+
+    /// Sends a GET request and returns the status line, the lowercase headers and the body
+    async fn get(&mut self, path: &str) -> Option<(String, Vec<String>, String)> {
+        let request = format!("GET {} HTTP/1.1\r\nhost: localhost\r\ncontent-length: 0\r\n\r\n", path);
+        self.0.get_mut().write_all(request.as_bytes()).await.ok()?;
+
+        let mut status = String::new();
+        if self.0.read_line(&mut status).await.ok()? == 0 {
+            return None;
+        }
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            let _ = self.0.read_line(&mut line).await.ok()?;
+            let line = line.trim_end().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            headers.push(line);
+        }
+        let len = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("content-length: "))
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; len];
+        self.0.read_exact(&mut body).await.ok()?;
+        Some((status.trim_end().to_owned(), headers, String::from_utf8(body).ok()?))
+    }
+}
+
+/// Holds the requests passing it until released, and lets the test know when one arrived
+#[derive(Clone)]
+struct Gate {
+    arrived: Sender<()>,
+    release: Receiver<()>,
+}
+
+/// The test side of a [Gate]
+struct GateControl {
+    arrived: Receiver<()>,
+    release: Sender<()>,
+}
+
+fn gate() -> (Gate, GateControl) {
+    let (arrived, arrivals) = channel::unbounded();
+    let (release, releases) = channel::unbounded();
+    (
+        Gate {
+            arrived,
+            release: releases,
+        },
+        GateControl {
+            arrived: arrivals,
+            release,
+        },
+    )
+}
+
+impl Gate {
+    async fn pass(&self) {
+        let _ = self.arrived.send(()).await;
+        let _ = self.release.recv().await;
+    }
+}
+
+impl GateControl {
+    async fn arrived(&self) {
+        self.arrived.recv().await.expect("arrival");
+    }
+
+    async fn release(&self) {
+        self.release.send(()).await.expect("release");
+    }
+}
+
+/// Holds the first request at the gate before passing it on, and notes when it is done
+struct GatedMiddleware {
+    gate: Gate,
+    gated: AtomicBool,
+    handled: Arc<AtomicBool>,
+}
+
+#[tide::utils::async_trait]
+impl Middleware<()> for GatedMiddleware {
+    async fn handle(&self, req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        if self.gated.swap(true, Ordering::SeqCst) {
+            return Ok(next.run(req).await);
+        }
+        self.gate.pass().await;
+        let res = next.run(req).await;
+        self.handled.store(true, Ordering::SeqCst);
+        Ok(res)
+    }
+}
+
+fn app(gate: Gate) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.at("/").get(|_| async { Ok("fast") });
+    app.at("/gated").get(move |_| {
+        let gate = gate.clone();
+        async move {
+            gate.pass().await;
+            Ok("gated")
+        }
+    });
+    app
+}
+
+/// Serves the app on a port picked by the system, returns its address, the trigger of the shutdown and the server
+fn serve(app: tide::Server<()>, config: ShutdownConfig) -> (SocketAddr, Sender<()>, task::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("listener");
+    let addr = listener.local_addr().expect("address");
+    let (shutdown, signal) = channel::bounded::<()>(1);
+    let server = task::spawn(async move {
+        let signal = async move {
+            let _ = signal.recv().await;
+        };
+        listen_until(app, listener, signal, config).await.expect("server");
+    });
+    (addr, shutdown, server)
+}
+
+/// Sends requests on the idle keep-alive connection until the server turns them away, returning the headers then
+async fn turned_away(idle: &mut Connection) -> Vec<String> {
+    loop {
+        let (status, headers, _) = idle.get("/").await.expect("response");
+        if status == "HTTP/1.1 503 Service Unavailable" {
+            return headers;
+        }
+        assert_eq!(status, "HTTP/1.1 200 OK");
+    }
+}
+
+#[async_std::test]
+async fn requests_in_flight_are_drained() {
+    let (gate, control) = gate();
+    let (addr, shutdown, server) = serve(app(gate), ShutdownConfig::default());
+    let mut idle = Connection::open(addr).await;
+
+    let request = task::spawn(async move { Connection::open(addr).await.get("/gated").await });
+    control.arrived().await;
+    shutdown.send(()).await.expect("shutdown");
+    let _ = turned_away(&mut idle).await;
+    control.release().await;
+    server.await;
+
+    let (status, headers, body) = request.await.expect("response");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, "gated");
+    assert!(headers.contains(&"connection: close".to_owned()), "{:?}", headers);
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[async_std::test]
+async fn requests_are_counted_before_the_middlewares_of_the_app() {
+    let (gate, control) = gate();
+    let handled = Arc::new(AtomicBool::new(false));
+    let mut app = app(gate.clone());
+    app.with(GatedMiddleware {
+        gate,
+        gated: AtomicBool::new(false),
+        handled: handled.clone(),
+    });
+    let (addr, shutdown, server) = serve(app, ShutdownConfig::default());
+    let mut idle = Connection::open(addr).await;
+
+    let request = task::spawn(async move { Connection::open(addr).await.get("/").await });
+    control.arrived().await;
+    shutdown.send(()).await.expect("shutdown");
+    let _ = turned_away(&mut idle).await;
+    control.release().await;
+    server.await;
+    assert!(handled.load(Ordering::SeqCst));
+
+    let (status, _, body) = request.await.expect("response");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, "fast");
+}
+
+#[async_std::test]
+async fn keep_alive_connections_are_turned_away_after_the_shutdown_started() {
+    let (gate, control) = gate();
+    let (addr, shutdown, server) = serve(app(gate), ShutdownConfig::default());
+
+    let mut idle = Connection::open(addr).await;
+    let (status, headers, _) = idle.get("/").await.expect("response");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(!headers.contains(&"connection: close".to_owned()), "{:?}", headers);
+
+    // keeps the server draining, while the idle connection is used again
+    let gated = task::spawn(async move { Connection::open(addr).await.get("/gated").await });
+    control.arrived().await;
+    shutdown.send(()).await.expect("shutdown");
+
+    let headers = turned_away(&mut idle).await;
+    assert!(headers.contains(&"connection: close".to_owned()), "{:?}", headers);
+    assert!(idle.get("/").await.is_none());
+
+    control.release().await;
+    server.await;
+    let (status, _, _) = gated.await.expect("response");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+}
+
+#[cfg(feature = "metrics")]
+#[async_std::test]
+async fn metrics_get_a_final_export() {
+    use std::sync::Mutex;
+
+    let (gate, _control) = gate();
+    let mut app = app(gate);
+    app.with(opentelemetry_tide::OpenTelemetryMetricsMiddleware::default());
+    let exported = Arc::new(Mutex::new(None));
+    let sink = exported.clone();
+    let config = ShutdownConfig {
+        metrics_sink: Some(Box::new(move |metrics| {
+            *sink.lock().expect("sink") = Some(metrics);
+        })),
+        ..Default::default()
+    };
+    let (addr, shutdown, server) = serve(app, config);
+
+    let _ = Connection::open(addr).await.get("/").await.expect("response");
+    shutdown.send(()).await.expect("shutdown");
+    server.await;
+
+    let exported = exported.lock().expect("export").take().expect("final export");
+    assert!(exported.contains("http_server_requests_count{"), "{}", exported);
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "tracing-interop")]
+
+mod common;
+
+use common::Traces;
+use opentelemetry_tide::OpenTelemetryTracingMiddleware;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[async_std::test]
+async fn tracing_spans_nest_under_the_server_span() {
+    let traces = Traces::new();
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(traces.sdk_tracer()));
+    // the test runs the request on this very thread
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+
+    let mut app = tide::new();
+    app.with(OpenTelemetryTracingMiddleware::new(traces.tracer()));
+    app.at("/orders").get(|_| async {
+        tracing::info_span!("db.query").in_scope(|| tracing::info!("querying"));
+        Ok("orders")
+    });
+
+    let _ = common::get(&app, "/orders").await;
+
+    let server = traces.span("GET http://localhost/orders");
+    let request = traces.span("tide.request");
+    let query = traces.span("db.query");
+    assert_eq!(request.parent_span_id, server.span_context.span_id());
+    assert_eq!(query.parent_span_id, request.span_context.span_id());
+    assert_eq!(request.span_context.trace_id(), server.span_context.trace_id());
+    assert_eq!(query.span_context.trace_id(), server.span_context.trace_id());
+    assert!(request.end_time <= server.end_time);
+}
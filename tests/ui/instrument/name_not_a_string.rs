@@ -0,0 +1,6 @@
+#[opentelemetry_tide::instrument(name = 42)]
+fn load(id: u64) -> u64 {
+    id
+}
+
+fn main() {}
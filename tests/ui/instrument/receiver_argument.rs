@@ -0,0 +1,10 @@
+struct Users;
+
+impl Users {
+    #[opentelemetry_tide::instrument(args(self))]
+    fn load(&self, id: u64) -> u64 {
+        id
+    }
+}
+
+fn main() {}
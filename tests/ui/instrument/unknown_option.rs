@@ -0,0 +1,6 @@
+#[opentelemetry_tide::instrument(level = "debug")]
+fn load(id: u64) -> u64 {
+    id
+}
+
+fn main() {}